@@ -3,7 +3,6 @@ extern crate criterion;
 extern crate my_iridium;
 
 use criterion::Criterion;
-use my_iridium::assembler::{PIE_HEADER_LENGTH, PIE_HEADER_PREFIX};
 use my_iridium::vm::VM;
 
 fn get_test_vm() -> VM {
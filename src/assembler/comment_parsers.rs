@@ -0,0 +1,51 @@
+use nom::multispace1;
+use nom::types::CompleteStr;
+
+/// Any run of whitespace, `; line comments` and `/* block comments */`, possibly empty
+named!(pub space<CompleteStr, CompleteStr>,
+    recognize!(
+        many0!(
+            alt!(multispace1 | parse_line_comment | parse_block_comment)
+        )
+    )
+);
+
+/// A comment from `;` to the end of the line
+named!(parse_line_comment<CompleteStr, CompleteStr>,
+    recognize!(
+        preceded!(char!(';'), take_till!(|c| c == '\n'))
+    )
+);
+
+/// A comment between `/*` and `*/`, which may span several lines
+named!(parse_block_comment<CompleteStr, CompleteStr>,
+    recognize!(
+        delimited!(tag!("/*"), take_until!("*/"), tag!("*/"))
+    )
+);
+
+/// What is left of `input` after leading whitespace and comments
+pub fn skip_space(input: &str) -> &str {
+    match space(CompleteStr(input)) {
+        Ok((rest, _)) => rest.0,
+        Err(_) => input,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_comments() {
+        assert_eq!(skip_space("  ; a comment\n  hlt"), "hlt");
+        assert_eq!(skip_space("/* one\n two */ hlt"), "hlt");
+        assert_eq!(skip_space("; a\n/* b */\n; c"), "");
+        assert_eq!(skip_space("hlt ; comment"), "hlt ; comment");
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        assert_eq!(skip_space(" /* never closed\nhlt"), "/* never closed\nhlt");
+    }
+}
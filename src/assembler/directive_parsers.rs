@@ -1,6 +1,7 @@
 use assembler::instruction_parsers::AssemblerInstruction;
 use assembler::label_parsers::parse_label_decl;
-use assembler::operand_parsers::parse_operand;
+use assembler::operand_parsers::{parse_identifier, parse_operand};
+use assembler::source::with_location;
 use assembler::Token;
 use nom::alpha;
 use nom::types::CompleteStr;
@@ -8,7 +9,7 @@ use nom::types::CompleteStr;
 
 /// Parser for directive
 named!(pub parse_directive_decl<CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
             tag!(".") >>
             name: alpha >>
@@ -24,41 +25,76 @@ named!(pub parse_directive_decl<CompleteStr, Token>,
 /// Will try to parse out any of the Directive forms
 named!(pub parse_directive<CompleteStr, AssemblerInstruction>,
     do_parse!(
-        ins: alt!(
-            parse_directive_combined
-        ) >>
+        ins: call!(with_location, parse_directive_combined) >>
         (
-            ins
+            {
+                let (mut ins, location) = ins;
+                ins.locations.instruction = location;
+                ins
+            }
         )
     )
 );
 
 named!(parse_directive_combined<CompleteStr, AssemblerInstruction>,
-    ws!(
+    ws_comments!(
         do_parse!(
-            label: opt!(parse_label_decl) >>
-            name: parse_directive_decl >>
-            operand1: opt!(parse_operand) >>
-            operand2: opt!(parse_operand) >>
-            operand3: opt!(parse_operand) >>
+            label: opt!(call!(with_location, parse_label_decl)) >>
+            name: call!(with_location, parse_directive_decl) >>
+            declared: cond!(takes_name(&name.0), call!(with_location, parse_name_decl)) >>
+            path: cond!(is_include(&name.0), opt!(call!(with_location, parse_include_path))) >>
+            operands: many0!(call!(with_location, parse_operand)) >>
             (
-                AssemblerInstruction{
-                    opcode: None,
-                    directive: Some(name),
-                    label,
-                    operand1,
-                    operand2,
-                    operand3,
+                {
+                    let operands = declared.into_iter().chain(path.and_then(|path| path)).chain(operands).collect();
+                    AssemblerInstruction::from_located_list(label, Some(name), None, operands)
                 }
             )
         )
     )
 );
 
+/// `.equ`, `.const`, `.macro`, `.global`, `.extern` and `.reg` are followed by the bare name of what they declare
+fn takes_name(directive: &Token) -> bool {
+    match directive {
+        Token::Directive { name } => ["equ", "const", "macro", "global", "extern", "reg"].contains(&name.as_str()),
+        _ => false,
+    }
+}
+
+fn is_include(directive: &Token) -> bool {
+    match directive {
+        Token::Directive { name } => name == "include",
+        _ => false,
+    }
+}
+
+/// Parser for the path in `.include "lib/util.iasm"`, quoted like a path in C
+named!(parse_include_path<CompleteStr, Token>,
+    ws_comments!(
+        do_parse!(
+            tag!("\"") >>
+            path: take_until!("\"") >>
+            tag!("\"") >>
+            (
+                Token::IrString { name: path.to_string() }
+            )
+        )
+    )
+);
+
+/// Parser for the name in `.equ NAME #value` or `.macro NAME \param`
+named!(parse_name_decl<CompleteStr, Token>,
+    ws_comments!(
+        map!(parse_identifier, |name| Token::Name { name: name.to_string() })
+    )
+);
+
 mod tests {
-    #![allow(unused_imports)]
+    #![allow(unused_imports, clippy::bool_assert_comparison)]
 
     use super::*;
+    use assembler::instruction_parsers::InstructionLocations;
 
     #[test]
     fn test_parse_directive_decl() {
@@ -83,6 +119,8 @@ mod tests {
             }),
             operand2: None,
             operand3: None,
+            extra_operands: vec![],
+            locations: InstructionLocations::default(),
         };
 
         let result = parse_directive_combined(CompleteStr("test: .asciiz 'Hello'"));
@@ -90,4 +128,31 @@ mod tests {
         let (_, directive) = result.unwrap();
         assert_eq!(directive, directive2);
     }
+
+    #[test]
+    fn test_parse_directive_operand_list() {
+        let result = parse_directive(CompleteStr("table: .byte #1 #2 #3 #4 #5\n.code"));
+        let (rest, directive) = result.unwrap();
+        assert_eq!(rest, CompleteStr(".code"));
+        assert_eq!(directive.operand3, Some(Token::IntegerOperand { value: 3 }));
+        assert_eq!(
+            directive.extra_operands,
+            vec![Token::IntegerOperand { value: 4 }, Token::IntegerOperand { value: 5 }]
+        );
+        assert_eq!(directive.operands().len(), 5);
+    }
+
+    #[test]
+    fn test_parse_constant_declaration() {
+        let result = parse_directive(CompleteStr(".equ BUF_SIZE #16*2\nhlt"));
+        let (rest, directive) = result.unwrap();
+        assert_eq!(rest, CompleteStr("hlt"));
+        assert_eq!(directive.operand1, Some(Token::Name { name: "BUF_SIZE".to_string() }));
+        assert_eq!(directive.operand2, Some(Token::IntegerOperand { value: 32 }));
+
+        // Other directives don't take a bare name, which would swallow the label on the next line
+        let result = parse_directive(CompleteStr(".data\nhello: .asciiz 'Hi'"));
+        let (rest, _) = result.unwrap();
+        assert_eq!(rest, CompleteStr("hello: .asciiz 'Hi'"));
+    }
 }
@@ -1,41 +1,233 @@
 use std::error::Error;
+use assembler::source::SourceLocation;
+use instruction::{Opcode, OperandKind};
 use std::fmt;
+use std::io;
 
+/// Errors found while assembling, each pointing at where in the source it happened
 #[derive(Debug, Clone)]
 pub enum AssemblerError {
-    NoSegmentDeclarationFound { instruction: u32 },
-    StringConstantDeclaredWithoutLabel { instruction: u32 },
-    SymbolAlreadyDeclared,
-    UnknownDirectiveFound { directive: String },
-    NonOpcodeInOpcodeField,
-    InsufficientSections,
-    ParseError { error: String },
+    NoSegmentDeclarationFound { location: SourceLocation },
+    StringConstantDeclaredWithoutLabel { location: SourceLocation },
+    SymbolAlreadyDeclared { name: String, location: SourceLocation },
+    UnknownDirectiveFound { directive: String, location: SourceLocation },
+    NonOpcodeInOpcodeField { location: SourceLocation },
+    NonOperandInOperandField { location: SourceLocation },
+    UnknownLabel { name: String, location: SourceLocation },
+    IntegerOperandOutOfRange { value: i64, bits: u8, location: SourceLocation },
+    UnexpectedFloatOperand { value: f64, location: SourceLocation },
+    UnpooledFloatConstant { value: f64, location: SourceLocation },
+    InsufficientSections { location: SourceLocation },
+    ParseError { error: String, location: SourceLocation },
+    WrongOperandCount { opcode: Opcode, min: usize, max: usize, found: usize, location: SourceLocation },
+    WrongOperandType { opcode: Opcode, position: usize, expected: OperandKind, location: SourceLocation },
+    RegisterOutOfRange { register: u8, location: SourceLocation },
+    UnknownOpcode { name: String, suggestion: Option<&'static str>, location: SourceLocation },
+    WrongDirectiveOperand { directive: String, expected: &'static str, location: SourceLocation },
+    InvalidAlignment { value: i64, location: SourceLocation },
+    UnknownConstant { name: String, location: SourceLocation },
+    InvalidExpression { error: &'static str, location: SourceLocation },
+    InvalidMacro { error: &'static str, location: SourceLocation },
+    WrongMacroArgumentCount { name: String, expected: usize, found: usize, location: SourceLocation },
+    UnknownMacroParameter { name: String, location: SourceLocation },
+    IncludeNotFound { path: String, location: SourceLocation },
+    IncludeCycle { path: String, location: SourceLocation },
+    UnreadableFile { path: String, error: io::ErrorKind, location: SourceLocation },
+    UnlinkedExternal { name: String, location: SourceLocation },
+    InvalidLocalLabel { error: &'static str, location: SourceLocation },
+    WrongPseudoOperands { name: String, expected: &'static str, location: SourceLocation },
+    UnknownRegister { name: String, location: SourceLocation },
+    InvalidConversionMode { mode: i64, location: SourceLocation },
+    FloatOperandOutOfRange { location: SourceLocation },
+}
+
+impl AssemblerError {
+    /// Where in the source the error was found
+    pub fn location(&self) -> &SourceLocation {
+        match self {
+            AssemblerError::NoSegmentDeclarationFound { location }
+            | AssemblerError::StringConstantDeclaredWithoutLabel { location }
+            | AssemblerError::SymbolAlreadyDeclared { location, .. }
+            | AssemblerError::UnknownDirectiveFound { location, .. }
+            | AssemblerError::NonOpcodeInOpcodeField { location }
+            | AssemblerError::NonOperandInOperandField { location }
+            | AssemblerError::UnknownLabel { location, .. }
+            | AssemblerError::IntegerOperandOutOfRange { location, .. }
+            | AssemblerError::UnexpectedFloatOperand { location, .. }
+            | AssemblerError::UnpooledFloatConstant { location, .. }
+            | AssemblerError::InsufficientSections { location }
+            | AssemblerError::ParseError { location, .. }
+            | AssemblerError::WrongOperandCount { location, .. }
+            | AssemblerError::WrongOperandType { location, .. }
+            | AssemblerError::RegisterOutOfRange { location, .. }
+            | AssemblerError::UnknownOpcode { location, .. }
+            | AssemblerError::WrongDirectiveOperand { location, .. }
+            | AssemblerError::InvalidAlignment { location, .. }
+            | AssemblerError::UnknownConstant { location, .. }
+            | AssemblerError::InvalidExpression { location, .. }
+            | AssemblerError::InvalidMacro { location, .. }
+            | AssemblerError::WrongMacroArgumentCount { location, .. }
+            | AssemblerError::UnknownMacroParameter { location, .. }
+            | AssemblerError::IncludeNotFound { location, .. }
+            | AssemblerError::IncludeCycle { location, .. }
+            | AssemblerError::UnreadableFile { location, .. }
+            | AssemblerError::UnlinkedExternal { location, .. }
+            | AssemblerError::InvalidLocalLabel { location, .. }
+            | AssemblerError::WrongPseudoOperands { location, .. }
+            | AssemblerError::UnknownRegister { location, .. }
+            | AssemblerError::InvalidConversionMode { location, .. }
+            | AssemblerError::FloatOperandOutOfRange { location } => location,
+        }
+    }
+
+    /// Formats the error the way rustc does: the message, the position,
+    /// then the source line with carets under the offending span.
+    /// Errors inside a macro's body also show the calls the body was expanded at
+    pub fn render(&self) -> String {
+        let location = self.location();
+        let mut rendered = format!("error: {}\n{}", self, snippet(location, "-->", '^', ""));
+
+        let mut expansion = &location.expansion;
+        while let Some(ref outer) = *expansion {
+            let label = format!(" in this expansion of `{}`", outer.name);
+            rendered.push_str(&snippet(&outer.call, ":::", '-', &label));
+            expansion = &outer.call.expansion;
+        }
+        rendered
+    }
+}
+
+/// The position of `location`, then its source line with `marker` under the span
+fn snippet(location: &SourceLocation, arrow: &str, marker: char, label: &str) -> String {
+    let number = location.line.to_string();
+    let gutter = " ".repeat(number.len());
+
+    // Keep tabs so the carets line up with the source line however tabs are displayed
+    let prefix: String = location
+        .source_line
+        .chars()
+        .take(location.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let available = location.source_line.chars().count().saturating_sub(location.column - 1);
+    let width = (location.end - location.start).min(available).max(1);
+
+    format!(
+        "{}{} {}\n{} |\n{} | {}\n{} | {}{}{}\n",
+        gutter,
+        arrow,
+        location,
+        gutter,
+        number,
+        location.source_line,
+        gutter,
+        prefix,
+        marker.to_string().repeat(width),
+        label
+    )
 }
 
 impl fmt::Display for AssemblerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            AssemblerError::NoSegmentDeclarationFound { instruction } => {
-                f.write_str(&format!("No segment declaration (e.g., .code, .data) prior to finding an opcode or other directive. Instruction # was {}:", instruction))
+            AssemblerError::NoSegmentDeclarationFound { .. } => {
+                f.write_str("No segment declaration (e.g., .code, .data) prior to finding an opcode or other directive.")
             }
-            AssemblerError::StringConstantDeclaredWithoutLabel { instruction } => {
-                f.write_str(&format!("Found a string constant without a corresponding label. Instruction # was {}: ", instruction))
+            AssemblerError::StringConstantDeclaredWithoutLabel { .. } => {
+                f.write_str("Found a string constant without a corresponding label.")
             }
-            AssemblerError::SymbolAlreadyDeclared => {
-                f.write_str("This symbol was previously declared.")
+            AssemblerError::SymbolAlreadyDeclared { ref name, .. } => {
+                f.write_str(&format!("Symbol {} was previously declared.", name))
             }
-            AssemblerError::UnknownDirectiveFound { ref directive } => {
+            AssemblerError::UnknownDirectiveFound { ref directive, .. } => {
                 f.write_str(&format!("Invalid or unknown directive found. Directive name was: {}", directive))
             }
-            AssemblerError::NonOpcodeInOpcodeField => {
+            AssemblerError::NonOpcodeInOpcodeField { .. } => {
                 f.write_str("An non-opcode was found in an opcode field")
             }
-            AssemblerError::InsufficientSections => {
+            AssemblerError::NonOperandInOperandField { .. } => {
+                f.write_str("A non-operand was found in an operand field")
+            }
+            AssemblerError::UnknownLabel { ref name, .. } => {
+                f.write_str(&format!("Label @{} was used but never declared", name))
+            }
+            AssemblerError::IntegerOperandOutOfRange { value, bits, .. } => {
+                f.write_str(&format!("Integer operand {} does not fit in {} bits", value, bits))
+            }
+            AssemblerError::UnexpectedFloatOperand { value, .. } => {
+                f.write_str(&format!("Float operand {} can only be used with loadf64", value))
+            }
+            AssemblerError::UnpooledFloatConstant { value, .. } => {
+                f.write_str(&format!("Float constant {} was not placed in the read-only section", value))
+            }
+            AssemblerError::InsufficientSections { .. } => {
                 f.write_str("Less than two sections/segments were found in the code")
             }
-            AssemblerError::ParseError { ref error } => {
+            AssemblerError::ParseError { ref error, .. } => {
                 f.write_str(&format!("There was an error parsing the code: {}", error))
             }
+            AssemblerError::WrongOperandCount { opcode, min, max, found, .. } => {
+                let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+                f.write_str(&format!("`{}` expects {} operand(s), found {}", opcode.mnemonic(), expected, found))
+            }
+            AssemblerError::WrongOperandType { opcode, position, expected, .. } => {
+                f.write_str(&format!("Operand {} of `{}` should be {}", position, opcode.mnemonic(), expected))
+            }
+            AssemblerError::RegisterOutOfRange { register, .. } => {
+                f.write_str(&format!("Register ${} does not exist, registers go from $0 to $31", register))
+            }
+            AssemblerError::UnknownOpcode { ref name, suggestion, .. } => match suggestion {
+                Some(suggestion) => f.write_str(&format!("Unknown opcode `{}`, did you mean `{}`?", name, suggestion)),
+                None => f.write_str(&format!("Unknown opcode `{}`", name)),
+            },
+            AssemblerError::WrongDirectiveOperand { ref directive, expected, .. } => {
+                f.write_str(&format!("`.{}` expects {}", directive, expected))
+            }
+            AssemblerError::InvalidAlignment { value, .. } => {
+                f.write_str(&format!("Alignment {} is not a power of two", value))
+            }
+            AssemblerError::UnknownConstant { ref name, .. } => {
+                f.write_str(&format!("Constant {} was used but never declared with .equ", name))
+            }
+            AssemblerError::InvalidExpression { error, .. } => {
+                f.write_str(&format!("Invalid expression: {}", error))
+            }
+            AssemblerError::InvalidMacro { error, .. } => {
+                f.write_str(&format!("Invalid macro: {}", error))
+            }
+            AssemblerError::WrongMacroArgumentCount { ref name, expected, found, .. } => {
+                f.write_str(&format!("Macro `{}` expects {} argument(s), found {}", name, expected, found))
+            }
+            AssemblerError::UnknownMacroParameter { ref name, .. } => {
+                f.write_str(&format!("\\{} is not a parameter of the macro it is used in", name))
+            }
+            AssemblerError::IncludeNotFound { ref path, .. } => {
+                f.write_str(&format!("Cannot find the included file \"{}\"", path))
+            }
+            AssemblerError::IncludeCycle { ref path, .. } => {
+                f.write_str(&format!("File \"{}\" includes itself", path))
+            }
+            AssemblerError::UnreadableFile { ref path, error, .. } => {
+                f.write_str(&format!("Cannot read \"{}\": {}", path, error))
+            }
+            AssemblerError::UnlinkedExternal { ref name, .. } => {
+                f.write_str(&format!("{} is declared .extern, so the file has to be assembled as an object and linked", name))
+            }
+            AssemblerError::InvalidLocalLabel { error, .. } => {
+                f.write_str(&format!("Invalid local label: {}", error))
+            }
+            AssemblerError::WrongPseudoOperands { ref name, expected, .. } => {
+                f.write_str(&format!("`{}` expects {}", name, expected))
+            }
+            AssemblerError::UnknownRegister { ref name, .. } => {
+                f.write_str(&format!("Register ${} was never declared with .reg", name))
+            }
+            AssemblerError::InvalidConversionMode { mode, .. } => {
+                f.write_str(&format!("Float to integer conversion mode {} does not exist, modes go from 0 to 3", mode))
+            }
+            AssemblerError::FloatOperandOutOfRange { .. } => {
+                f.write_str("Float operand is too large for a 64 bits float")
+            }
         }
     }
 }
@@ -49,21 +241,96 @@ impl Error for AssemblerError {
             AssemblerError::StringConstantDeclaredWithoutLabel { .. } => {
                 "Found a string constant without a corresponding label."
             }
-            AssemblerError::SymbolAlreadyDeclared => {
+            AssemblerError::SymbolAlreadyDeclared { .. } => {
                 "This symbol was previously declared."
             }
             AssemblerError::UnknownDirectiveFound { .. } => {
                 "Invalid or unknown directive found."
             }
-            AssemblerError::NonOpcodeInOpcodeField => {
+            AssemblerError::NonOpcodeInOpcodeField { .. } => {
                 "A non-opcode was found in an opcode field"
             }
-            AssemblerError::InsufficientSections => {
+            AssemblerError::NonOperandInOperandField { .. } => {
+                "A non-operand was found in an operand field"
+            }
+            AssemblerError::UnknownLabel { .. } => {
+                "A label was used but never declared"
+            }
+            AssemblerError::IntegerOperandOutOfRange { .. } => {
+                "Integer operand does not fit in its field"
+            }
+            AssemblerError::UnexpectedFloatOperand { .. } => {
+                "Float operands can only be used with loadf64"
+            }
+            AssemblerError::UnpooledFloatConstant { .. } => {
+                "Float constant was not placed in the read-only section"
+            }
+            AssemblerError::InsufficientSections { .. } => {
                 "Less than two sections/segments were found in the code"
             }
             AssemblerError::ParseError { .. } => {
                 "There was an error parsing the code"
             }
+            AssemblerError::WrongOperandCount { .. } => {
+                "Wrong number of operands for the opcode"
+            }
+            AssemblerError::WrongOperandType { .. } => {
+                "Operand is not of the kind the opcode expects"
+            }
+            AssemblerError::RegisterOutOfRange { .. } => {
+                "Register does not exist"
+            }
+            AssemblerError::UnknownOpcode { .. } => {
+                "Unknown opcode"
+            }
+            AssemblerError::WrongDirectiveOperand { .. } => {
+                "Operands do not match the directive"
+            }
+            AssemblerError::InvalidAlignment { .. } => {
+                "Alignment is not a power of two"
+            }
+            AssemblerError::UnknownConstant { .. } => {
+                "A constant was used but never declared"
+            }
+            AssemblerError::InvalidExpression { .. } => {
+                "The expression cannot be evaluated"
+            }
+            AssemblerError::InvalidMacro { .. } => {
+                "Invalid macro definition or call"
+            }
+            AssemblerError::WrongMacroArgumentCount { .. } => {
+                "Wrong number of arguments for the macro"
+            }
+            AssemblerError::UnknownMacroParameter { .. } => {
+                "Macro parameter is not declared"
+            }
+            AssemblerError::IncludeNotFound { .. } => {
+                "The included file was not found"
+            }
+            AssemblerError::IncludeCycle { .. } => {
+                "A file includes itself"
+            }
+            AssemblerError::UnreadableFile { .. } => {
+                "A source file cannot be read"
+            }
+            AssemblerError::UnlinkedExternal { .. } => {
+                "External symbols need the file to be assembled as an object"
+            }
+            AssemblerError::InvalidLocalLabel { .. } => {
+                "A local label is used where it can't be resolved"
+            }
+            AssemblerError::WrongPseudoOperands { .. } => {
+                "Wrong operands for the pseudo-instruction"
+            }
+            AssemblerError::UnknownRegister { .. } => {
+                "Register alias is not declared"
+            }
+            AssemblerError::InvalidConversionMode { .. } => {
+                "Float to integer conversion mode does not exist"
+            }
+            AssemblerError::FloatOperandOutOfRange { .. } => {
+                "Float operand is too large"
+            }
         }
     }
 }
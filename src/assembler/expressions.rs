@@ -0,0 +1,174 @@
+use assembler::errors::AssemblerError;
+use assembler::source::SourceLocation;
+use assembler::symbols::SymbolTable;
+
+/// An integer operand the assembler computes, such as `#BUF_SIZE*2+1` or `@table+4`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    /// A name given a value by `.equ` or `.const`
+    Constant(String),
+    /// The read-only offset of a data label or the address of a code label, `@name`
+    Label(String),
+    /// How many bytes of data a data label names, `len(@name)`
+    Len(String),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Expr {
+    /// Computes the value, looking names up in `symbols`.
+    /// Errors point at `location`, the operand the expression was written in
+    pub fn evaluate(&self, symbols: &SymbolTable, location: &SourceLocation) -> Result<i64, AssemblerError> {
+        let invalid = |error| AssemblerError::InvalidExpression { error, location: location.clone() };
+        let unknown_label = |name: &str| AssemblerError::UnknownLabel {
+            name: name.to_string(),
+            location: location.clone(),
+        };
+
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Constant(name) => symbols.constant_value(name).ok_or_else(|| AssemblerError::UnknownConstant {
+                name: name.clone(),
+                location: location.clone(),
+            }),
+            Expr::Label(name) => symbols.symbol_value(name).map(i64::from).ok_or_else(|| unknown_label(name)),
+            Expr::Len(name) => match symbols.symbol_size(name) {
+                Some(size) => Ok(i64::from(size)),
+                None if symbols.has_symbol(name) => Err(invalid("len() only applies to labels on data")),
+                None => Err(unknown_label(name)),
+            },
+            Expr::Negate(operand) => operand
+                .evaluate(symbols, location)?
+                .checked_neg()
+                .ok_or_else(|| invalid("the value overflows")),
+            Expr::Binary(op, left, right) => {
+                let left = left.evaluate(symbols, location)?;
+                let right = right.evaluate(symbols, location)?;
+                let result = match op {
+                    BinaryOp::Add => left.checked_add(right),
+                    BinaryOp::Sub => left.checked_sub(right),
+                    BinaryOp::Mul => left.checked_mul(right),
+                    BinaryOp::Div | BinaryOp::Rem if right == 0 => return Err(invalid("division by zero")),
+                    BinaryOp::Div => left.checked_div(right),
+                    BinaryOp::Rem => left.checked_rem(right),
+                };
+                result.ok_or_else(|| invalid("the value overflows"))
+            }
+        }
+    }
+
+    /// The value of an expression made only of numbers
+    pub fn literal_value(&self) -> Option<i64> {
+        self.evaluate(&SymbolTable::new(), &SourceLocation::default()).ok()
+    }
+
+    /// Calls `f` with the name of every label the expression uses, so they can be renamed
+    pub fn for_each_label(&mut self, f: &mut dyn FnMut(&mut String)) {
+        match self {
+            Expr::Label(name) | Expr::Len(name) => f(name),
+            Expr::Negate(operand) => operand.for_each_label(f),
+            Expr::Binary(_, left, right) => {
+                left.for_each_label(f);
+                right.for_each_label(f);
+            }
+            Expr::Number(_) | Expr::Constant(_) => {}
+        }
+    }
+
+    /// Splits `@label+4` or `@label-(SIZE*2)` into the label and what is added to its address.
+    /// Only these can be relocated when objects are linked
+    pub fn split_label(&self) -> Option<(String, Expr)> {
+        let add = |left: Expr, right: &Expr| Expr::Binary(BinaryOp::Add, Box::new(left), Box::new(right.clone()));
+        match self {
+            Expr::Label(name) => Some((name.clone(), Expr::Number(0))),
+            Expr::Binary(BinaryOp::Add, left, right) if !right.uses_label() => {
+                left.split_label().map(|(name, addend)| (name, add(addend, right)))
+            }
+            Expr::Binary(BinaryOp::Add, left, right) if !left.uses_label() => {
+                right.split_label().map(|(name, addend)| (name, add(addend, left)))
+            }
+            Expr::Binary(BinaryOp::Sub, left, right) if !right.uses_label() => left.split_label().map(|(name, addend)| {
+                (name, Expr::Binary(BinaryOp::Sub, Box::new(addend), right.clone()))
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether the expression is an address computed from a label, like `@loop+4`
+    pub fn uses_label(&self) -> bool {
+        match self {
+            Expr::Label(_) => true,
+            Expr::Negate(operand) => operand.uses_label(),
+            Expr::Binary(_, left, right) => left.uses_label() || right.uses_label(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::bool_assert_comparison)]
+
+    use super::*;
+    use assembler::symbols::{Symbol, SymbolType};
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary(op, Box::new(left), Box::new(right))
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new_constant("SIZE".to_string(), 16));
+        symbols.add_symbol(Symbol::new_with_offset("msg".to_string(), SymbolType::DataLabel, 8));
+        symbols.set_symbol_size("msg", 5);
+        let location = SourceLocation::default();
+
+        let expr = binary(BinaryOp::Add, binary(BinaryOp::Mul, Expr::Constant("SIZE".to_string()), Expr::Number(2)), Expr::Number(1));
+        assert_eq!(expr.evaluate(&symbols, &location).unwrap(), 33);
+        let expr = binary(BinaryOp::Sub, Expr::Label("msg".to_string()), Expr::Len("msg".to_string()));
+        assert_eq!(expr.evaluate(&symbols, &location).unwrap(), 3);
+        assert_eq!(Expr::Negate(Box::new(Expr::Number(4))).literal_value(), Some(-4));
+        assert_eq!(Expr::Label("msg".to_string()).literal_value(), None);
+    }
+
+    #[test]
+    fn test_split_label() {
+        let symbols = SymbolTable::new();
+        let location = SourceLocation::default();
+        let expr = binary(BinaryOp::Sub, binary(BinaryOp::Add, Expr::Number(8), Expr::Label("table".to_string())), Expr::Number(2));
+        let (name, addend) = expr.split_label().unwrap();
+        assert_eq!(name, "table");
+        assert_eq!(addend.evaluate(&symbols, &location).unwrap(), 6);
+
+        let expr = binary(BinaryOp::Sub, Expr::Number(8), Expr::Label("table".to_string()));
+        assert_eq!(expr.split_label(), None);
+        let expr = binary(BinaryOp::Mul, Expr::Label("table".to_string()), Expr::Number(2));
+        assert_eq!(expr.split_label(), None);
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        let symbols = SymbolTable::new();
+        let location = SourceLocation::default();
+
+        match Expr::Constant("MISSING".to_string()).evaluate(&symbols, &location) {
+            Err(AssemblerError::UnknownConstant { name, .. }) => assert_eq!(name, "MISSING"),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        let expr = binary(BinaryOp::Rem, Expr::Number(1), Expr::Number(0));
+        assert_eq!(expr.evaluate(&symbols, &location).unwrap_err().to_string(), "Invalid expression: division by zero");
+        let expr = binary(BinaryOp::Mul, Expr::Number(i64::MAX), Expr::Number(2));
+        assert_eq!(expr.evaluate(&symbols, &location).is_err(), true);
+    }
+}
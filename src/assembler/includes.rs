@@ -0,0 +1,163 @@
+use assembler::errors::AssemblerError;
+use assembler::instruction_parsers::AssemblerInstruction;
+use assembler::program_parsers::{parse_program, Program};
+use assembler::Token;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+struct Includer<'a> {
+    /// Directories searched for an included file that is not next to the file including it
+    search_path: &'a [PathBuf],
+    /// The files being included, outermost first, to find a file that includes itself
+    open: Vec<PathBuf>,
+    errors: Vec<AssemblerError>,
+}
+
+/// Replaces every `.include "path"` with the instructions of the file it names.
+/// `file` is where `program` was read from, if it was read from a file
+pub fn resolve_includes(program: Program, file: Option<&Path>, search_path: &[PathBuf]) -> Result<Program, Vec<AssemblerError>> {
+    let mut includer = Includer {
+        search_path,
+        open: file.and_then(|file| fs::canonicalize(file).ok()).into_iter().collect(),
+        errors: vec![],
+    };
+    let dir = file.and_then(Path::parent).unwrap_or_else(|| Path::new(""));
+    let mut instructions = vec![];
+    includer.include_all(program.instructions, dir, &mut instructions);
+
+    if includer.errors.is_empty() {
+        Ok(Program { instructions })
+    } else {
+        Err(includer.errors)
+    }
+}
+
+impl<'a> Includer<'a> {
+    /// Appends `instructions` to `out` with their includes resolved, `dir` being where they were read from
+    fn include_all(&mut self, instructions: Vec<AssemblerInstruction>, dir: &Path, out: &mut Vec<AssemblerInstruction>) {
+        for inst in instructions {
+            if inst.get_directive_name().as_deref() == Some("include") {
+                self.include(inst, dir, out);
+            } else {
+                out.push(inst);
+            }
+        }
+    }
+
+    fn include(&mut self, inst: AssemblerInstruction, dir: &Path, out: &mut Vec<AssemblerInstruction>) {
+        let (path, location) = match inst.operands().as_slice() {
+            [(Token::IrString { name }, location)] => (name.clone(), (*location).clone()),
+            _ => {
+                self.errors.push(AssemblerError::WrongDirectiveOperand {
+                    directive: "include".to_string(),
+                    expected: "a quoted path such as \"lib.iasm\"",
+                    location: inst.locations.directive.clone(),
+                });
+                return;
+            }
+        };
+
+        let found = match self.find(dir, &path) {
+            Some(found) => found,
+            None => {
+                self.errors.push(AssemblerError::IncludeNotFound { path, location });
+                return;
+            }
+        };
+        let canonical = fs::canonicalize(&found).unwrap_or_else(|_| found.clone());
+        if self.open.contains(&canonical) {
+            self.errors.push(AssemblerError::IncludeCycle { path, location });
+            return;
+        }
+        let source = match fs::read_to_string(&found) {
+            Ok(source) => source,
+            Err(e) => {
+                self.errors.push(AssemblerError::UnreadableFile { path, error: e.kind(), location });
+                return;
+            }
+        };
+
+        // Locations in the included file name it, so errors point into the right file
+        let program = match parse_program(&source, &found.display().to_string()) {
+            Ok(program) => program,
+            Err(errors) => {
+                self.errors.extend(errors);
+                return;
+            }
+        };
+
+        // A label on the include names the first instruction of the file
+        if let Some(label) = inst.label.clone() {
+            let mut label_only =
+                AssemblerInstruction::from_located(Some((label, inst.locations.label.clone())), None, None, [None, None, None]);
+            label_only.locations.instruction = inst.locations.instruction.clone();
+            out.push(label_only);
+        }
+        self.open.push(canonical);
+        self.include_all(program.instructions, found.parent().unwrap_or_else(|| Path::new("")), out);
+        self.open.pop();
+    }
+
+    /// Looks for `path` next to the including file, then in each directory of the search path
+    fn find(&self, dir: &Path, path: &str) -> Option<PathBuf> {
+        let mut candidates = vec![dir.join(path)];
+        candidates.extend(self.search_path.iter().map(|search_dir| search_dir.join(path)));
+        candidates.into_iter().find(|candidate| candidate.is_file())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// A fresh directory for the files of one test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("iridium-includes-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        dir
+    }
+
+    fn resolve(dir: &Path, file: &str, search_path: &[PathBuf]) -> Result<Program, Vec<AssemblerError>> {
+        let path = dir.join(file);
+        let source = fs::read_to_string(&path).unwrap();
+        let program = parse_program(&source, &path.display().to_string()).unwrap();
+        resolve_includes(program, Some(&path), search_path)
+    }
+
+    #[test]
+    fn test_resolve_includes() {
+        let dir = test_dir("resolve");
+        fs::write(dir.join("main.iasm"), ".include \"lib/util.iasm\"\nhlt\n").unwrap();
+        fs::write(dir.join("lib/util.iasm"), "inc $0\n.include \"more.iasm\"\n").unwrap();
+        fs::write(dir.join("lib/more.iasm"), "dec $0\n.include 'shared.iasm'\n").unwrap();
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(dir.join("shared/shared.iasm"), "nop\n").unwrap();
+
+        let program = resolve(&dir, "main.iasm", &[dir.join("shared")]).unwrap();
+        assert_eq!(program.instructions.len(), 4);
+        let files: Vec<String> = program.instructions.iter().map(|inst| inst.locations.instruction.file.to_string()).collect();
+        assert_eq!(files[0], dir.join("lib/util.iasm").display().to_string());
+        assert_eq!(files[1], dir.join("lib").join("more.iasm").display().to_string());
+        assert_eq!(files[2], dir.join("shared").join("shared.iasm").display().to_string());
+        assert_eq!(files[3], dir.join("main.iasm").display().to_string());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = test_dir("errors");
+        fs::write(dir.join("main.iasm"), ".include \"lib/a.iasm\"\n.include \"missing.iasm\"\n.include\n").unwrap();
+        fs::write(dir.join("lib/a.iasm"), ".include \"../main.iasm\"\n").unwrap();
+
+        let errors = resolve(&dir, "main.iasm", &[]).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].to_string(), "File \"../main.iasm\" includes itself");
+        assert_eq!(errors[0].location().file.as_ref(), dir.join("lib/a.iasm").display().to_string());
+        assert_eq!(errors[1].to_string(), "Cannot find the included file \"missing.iasm\"");
+        assert_eq!((errors[1].location().line, errors[1].location().column), (2, 10));
+        assert_eq!(errors[2].to_string(), "`.include` expects a quoted path such as \"lib.iasm\"");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
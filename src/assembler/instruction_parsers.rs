@@ -1,13 +1,18 @@
 use assembler::label_parsers::*;
 use assembler::opcode_parsers::*;
 use assembler::operand_parsers::*;
+use assembler::register_parsers::RegisterFile;
 use assembler::symbols::*;
-use assembler::Token;
+use assembler::errors::AssemblerError;
+use assembler::expressions::Expr;
+use assembler::source::{with_location, SourceLocation};
+use assembler::{Token, INSTRUCTION_LENGTH};
+use instruction::{closest_mnemonic, Opcode, OperandKind};
+use vm::REGISTER_COUNT;
 use nom::types::CompleteStr;
-use std::process;
 
 /// Stores a line assemble instruction
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct AssemblerInstruction {
     pub label: Option<Token>,
     pub directive: Option<Token>,
@@ -15,48 +20,366 @@ pub struct AssemblerInstruction {
     pub operand1: Option<Token>,
     pub operand2: Option<Token>,
     pub operand3: Option<Token>,
+    /// Operands after the third one, which only data directives such as `.byte` take
+    pub extra_operands: Vec<Token>,
+    /// Where the instruction and each of its tokens were written
+    pub locations: InstructionLocations,
+}
+
+/// Source locations of an instruction and of each of its tokens.
+/// Tokens that are missing keep a default location
+#[derive(Debug, Clone, Default)]
+pub struct InstructionLocations {
+    pub instruction: SourceLocation,
+    pub label: SourceLocation,
+    pub directive: SourceLocation,
+    pub opcode: SourceLocation,
+    pub operands: [SourceLocation; 3],
+    pub extra_operands: Vec<SourceLocation>,
+}
+
+impl InstructionLocations {
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut SourceLocation> {
+        vec![&mut self.instruction, &mut self.label, &mut self.directive, &mut self.opcode]
+            .into_iter()
+            .chain(self.operands.iter_mut())
+            .chain(self.extra_operands.iter_mut())
+    }
+}
+
+/// Instructions are the same when their tokens are, wherever they were written
+impl PartialEq for AssemblerInstruction {
+    fn eq(&self, other: &Self) -> bool {
+        self.label == other.label
+            && self.directive == other.directive
+            && self.opcode == other.opcode
+            && self.operand1 == other.operand1
+            && self.operand2 == other.operand2
+            && self.operand3 == other.operand3
+            && self.extra_operands == other.extra_operands
+    }
+}
+
+/// Splits a parsed token from where it was found
+fn split_location(located: Option<(Token, SourceLocation)>) -> (Option<Token>, SourceLocation) {
+    match located {
+        Some((token, location)) => (Some(token), location),
+        None => (None, SourceLocation::default()),
+    }
 }
 
 impl AssemblerInstruction {
+    /// Builds an instruction out of tokens that know where they were found
+    pub fn from_located(
+        label: Option<(Token, SourceLocation)>,
+        directive: Option<(Token, SourceLocation)>,
+        opcode: Option<(Token, SourceLocation)>,
+        operands: [Option<(Token, SourceLocation)>; 3],
+    ) -> Self {
+        let [operand1, operand2, operand3] = operands;
+        let (label, label_location) = split_location(label);
+        let (directive, directive_location) = split_location(directive);
+        let (opcode, opcode_location) = split_location(opcode);
+        let (operand1, operand1_location) = split_location(operand1);
+        let (operand2, operand2_location) = split_location(operand2);
+        let (operand3, operand3_location) = split_location(operand3);
+        Self {
+            label,
+            directive,
+            opcode,
+            operand1,
+            operand2,
+            operand3,
+            extra_operands: vec![],
+            locations: InstructionLocations {
+                instruction: SourceLocation::default(),
+                label: label_location,
+                directive: directive_location,
+                opcode: opcode_location,
+                operands: [operand1_location, operand2_location, operand3_location],
+                extra_operands: vec![],
+            },
+        }
+    }
+
+    /// Builds an instruction with any number of operands.
+    /// Only data directives and macro calls use more than three
+    pub fn from_located_list(
+        label: Option<(Token, SourceLocation)>,
+        directive: Option<(Token, SourceLocation)>,
+        opcode: Option<(Token, SourceLocation)>,
+        operands: Vec<(Token, SourceLocation)>,
+    ) -> Self {
+        let mut operands = operands.into_iter();
+        let first = [operands.next(), operands.next(), operands.next()];
+        let mut inst = AssemblerInstruction::from_located(label, directive, opcode, first);
+        for (token, location) in operands {
+            inst.extra_operands.push(token);
+            inst.locations.extra_operands.push(location);
+        }
+        inst
+    }
+
+    /// Every operand that was written, with where it was written, to change them
+    pub fn operands_mut(&mut self) -> Vec<(&mut Token, &mut SourceLocation)> {
+        vec![&mut self.operand1, &mut self.operand2, &mut self.operand3]
+            .into_iter()
+            .zip(self.locations.operands.iter_mut())
+            .filter_map(|(operand, location)| operand.as_mut().map(|token| (token, location)))
+            .chain(self.extra_operands.iter_mut().zip(self.locations.extra_operands.iter_mut()))
+            .collect()
+    }
+
+    /// Every operand that was written, with where it was written
+    pub fn operands(&self) -> Vec<(&Token, &SourceLocation)> {
+        [&self.operand1, &self.operand2, &self.operand3]
+            .iter()
+            .zip(self.locations.operands.iter())
+            .filter_map(|(operand, location)| operand.as_ref().map(|token| (token, location)))
+            .chain(self.extra_operands.iter().zip(self.locations.extra_operands.iter()))
+            .collect()
+    }
+
+    /// Turns the positions the parsers recorded into lines and columns of `source`
+    pub fn resolve_locations(&mut self, source: &str, file: &str) {
+        for location in self.locations.iter_mut() {
+            location.resolve(source, file);
+        }
+    }
+
     /// Translates instruction into bytes for eval.
-    pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Result<Vec<u8>, AssemblerError> {
+        if let Some(Token::Op { code }) = self.opcode {
+            self.check_operands(code)?;
+        }
+
+        // A `load` whose immediate needs more than 16 bits becomes LOAD of the low half
+        // followed by LOADHI of the high half
+        if let Some((register, value)) = self.wide_load() {
+            if value < i64::from(i32::MIN) || value > i64::from(u32::MAX) {
+                return Err(AssemblerError::IntegerOperandOutOfRange {
+                    value,
+                    bits: 32,
+                    location: self.locations.operands[1].clone(),
+                });
+            }
+            let bits = value as u32;
+            return Ok(vec![
+                u8::from(Opcode::LOAD), register, (bits >> 8) as u8, bits as u8,
+                u8::from(Opcode::LOADHI), register, (bits >> 24) as u8, (bits >> 16) as u8,
+            ]);
+        }
+
+        // A `loadf64` of anything a 16 bits integer can't hold reads the double from the read-only section
+        if let Some((register, value)) = self.float_constant() {
+            let offset = match symbols.float_constant_offset(value) {
+                Some(offset) => offset,
+                None => {
+                    return Err(AssemblerError::UnpooledFloatConstant {
+                        value,
+                        location: self.locations.operands[1].clone(),
+                    })
+                }
+            };
+            return Ok(vec![u8::from(Opcode::LOADCF64), register, (offset >> 8) as u8, offset as u8]);
+        }
+
         let mut results = vec![];
         // translate opcode
         if let Some(ref token) = self.opcode {
             match token {
-                Token::Op { code } => match code {
-                    _ => {
-                        // impl From<u8> for Opcode, convert any opcode into its integer with code as u8
-                        results.push(*code as u8);
-                    }
-                },
+                Token::Op { code } => {
+                    // Use the same numbering the VM decodes with, not the enum's declaration order
+                    results.push(u8::from(self.direct_form(*code)));
+                }
+                Token::UnknownOp { name } => {
+                    return Err(AssemblerError::UnknownOpcode {
+                        name: name.clone(),
+                        suggestion: closest_mnemonic(name),
+                        location: self.locations.opcode.clone(),
+                    })
+                }
                 _ => {
-                    println!("Non-opcode found in opcode field");
-                    process::exit(1);
+                    return Err(AssemblerError::NonOpcodeInOpcodeField {
+                        location: self.locations.opcode.clone(),
+                    })
                 }
             }
         }
 
         // translate operands
-        for operand in &[&self.operand1, &self.operand2, &self.operand3] {
+        let operands = [&self.operand1, &self.operand2, &self.operand3];
+        for (operand, location) in operands.iter().zip(self.locations.operands.iter()) {
             if let Some(token) = operand {
-                AssemblerInstruction::extract_operand(token, &mut results, symbols);
+                AssemblerInstruction::extract_operand(token, location, &mut results, symbols)?;
             }
         }
 
         // padding to 32 bits
-        while results.len() < 4 {
+        while results.len() < INSTRUCTION_LENGTH as usize {
             results.push(0);
         }
 
-        results
+        Ok(results)
+    }
+
+    /// Number of bytes this instruction takes up in the code section
+    pub fn size(&self) -> u32 {
+        if self.wide_load().is_some() {
+            2 * INSTRUCTION_LENGTH
+        } else if self.is_opcode() {
+            INSTRUCTION_LENGTH
+        } else {
+            0
+        }
+    }
+
+    /// The register and value of a `load` whose immediate does not fit in 16 bits
+    fn wide_load(&self) -> Option<(u8, i64)> {
+        match (&self.opcode, &self.operand1, &self.operand2) {
+            (
+                Some(Token::Op { code: Opcode::LOAD }),
+                Some(Token::Register { reg_num, .. }),
+                Some(Token::IntegerOperand { value }),
+            ) if *value < 0 || *value > i64::from(u16::MAX) => Some((*reg_num, *value)),
+            _ => None,
+        }
+    }
+
+    /// The register and value of a `loadf64` that has to go through the read-only section,
+    /// because its immediate is a float or an integer outside of 16 bits
+    pub fn float_constant(&self) -> Option<(u8, f64)> {
+        match (&self.opcode, &self.operand1, &self.operand2) {
+            (
+                Some(Token::Op { code: Opcode::LOADF64 }),
+                Some(Token::Register { reg_num, .. }),
+                Some(Token::FloatOperand { value }),
+            ) => Some((*reg_num, *value)),
+            (
+                Some(Token::Op { code: Opcode::LOADF64 }),
+                Some(Token::Register { reg_num, .. }),
+                Some(Token::IntegerOperand { value }),
+            ) if *value < 0 || *value > i64::from(u16::MAX) => Some((*reg_num, *value as f64)),
+            _ => None,
+        }
+    }
+
+    /// Checks the operands against the signature of the opcode they are encoded for
+    fn check_operands(&self, code: Opcode) -> Result<(), AssemblerError> {
+        let kinds = self.direct_form(code).operands();
+        let operands = self.operands();
+
+        let required = self.direct_form(code).required_operands();
+        if operands.len() < required || operands.len() > kinds.len() {
+            // Point at the first operand too many, or at the whole instruction if some are missing
+            let location = match operands.get(kinds.len()) {
+                Some((_, location)) => (*location).clone(),
+                None => self.locations.instruction.clone(),
+            };
+            return Err(AssemblerError::WrongOperandCount {
+                opcode: code,
+                min: required,
+                max: kinds.len(),
+                found: operands.len(),
+                location,
+            });
+        }
+
+        for (position, ((token, location), kind)) in operands.iter().zip(kinds.iter()).enumerate() {
+            let accepted = match (token, kind) {
+                (Token::Register { reg_num, file }, OperandKind::IntRegister)
+                | (Token::Register { reg_num, file }, OperandKind::FloatRegister) => {
+                    if *reg_num as usize >= REGISTER_COUNT {
+                        return Err(AssemblerError::RegisterOutOfRange {
+                            register: *reg_num,
+                            location: (*location).clone(),
+                        });
+                    }
+                    // `$f1` can't be given where an integer register is expected, nor `$t0` where a float one is
+                    !matches!(
+                        (file, kind),
+                        (Some(RegisterFile::Float), OperandKind::IntRegister)
+                            | (Some(RegisterFile::Int), OperandKind::FloatRegister)
+                    )
+                }
+                (Token::IntegerOperand { .. }, kind) | (Token::Expression { .. }, kind) => {
+                    *kind != OperandKind::IntRegister && *kind != OperandKind::FloatRegister
+                }
+                (Token::LabelUsage { .. }, kind) => kind.width() == 2,
+                // Floats only fit `loadf64`, whose immediate the first pass moves to the read-only section
+                (Token::FloatOperand { value }, OperandKind::Immediate16) if code == Opcode::LOADF64 => {
+                    // A literal such as `#1e400` overflows to infinity
+                    if !value.is_finite() {
+                        return Err(AssemblerError::FloatOperandOutOfRange { location: (*location).clone() });
+                    }
+                    true
+                }
+                _ => false,
+            };
+            if !accepted {
+                return Err(AssemblerError::WrongOperandType {
+                    opcode: code,
+                    position: position + 1,
+                    expected: *kind,
+                    location: (*location).clone(),
+                });
+            }
+        }
+
+        // The VM only knows the rounding modes 0 to 3 of `ftoi`
+        if let (Opcode::FTOI, Some((Token::IntegerOperand { value }, location))) = (code, operands.get(2)) {
+            if *value < 0 || *value > 3 {
+                return Err(AssemblerError::InvalidConversionMode { mode: *value, location: (*location).clone() });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `jmp @label` and `jmpe @label` jump straight to the label's address instead of through a register
+    fn direct_form(&self, code: Opcode) -> Opcode {
+        let to_label = match &self.operand1 {
+            Some(Token::LabelUsage { .. }) => true,
+            Some(Token::Expression { expr }) => expr.uses_label(),
+            _ => false,
+        };
+        match code {
+            Opcode::JMP if to_label => Opcode::DJMP,
+            Opcode::JMPE if to_label => Opcode::DJMPE,
+            _ => code,
+        }
     }
 
-    fn extract_operand(t: &Token, results: &mut Vec<u8>, symbols: &SymbolTable) {
+    fn extract_operand(
+        t: &Token,
+        location: &SourceLocation,
+        results: &mut Vec<u8>,
+        symbols: &SymbolTable,
+    ) -> Result<(), AssemblerError> {
         match t {
-            Token::Register { reg_num } => { results.push(*reg_num) },
+            Token::Register { reg_num, .. } => { results.push(*reg_num) },
+
+            // A trailing immediate after two registers, like the offset of `loadw $0 $1 #4`,
+            // only has one byte left in the instruction
+            Token::IntegerOperand { value } if results.len() == INSTRUCTION_LENGTH as usize - 1 => {
+                if *value < 0 || *value > i64::from(u8::MAX) {
+                    return Err(AssemblerError::IntegerOperandOutOfRange {
+                        value: *value,
+                        bits: 8,
+                        location: location.clone(),
+                    });
+                }
+                results.push(*value as u8);
+            }
 
             Token::IntegerOperand { value } => {
+                if *value < 0 || *value > i64::from(u16::MAX) {
+                    return Err(AssemblerError::IntegerOperandOutOfRange {
+                        value: *value,
+                        bits: 16,
+                        location: location.clone(),
+                    });
+                }
                 let converted = *value as u16;
                 let byte1 = converted;
                 let byte2 = converted >> 8;
@@ -66,25 +389,79 @@ impl AssemblerInstruction {
             }
 
             Token::FloatOperand { value } => {
-                unimplemented!();
+                return Err(AssemblerError::UnexpectedFloatOperand {
+                    value: *value,
+                    location: location.clone(),
+                });
+            }
+
+            Token::Expression { expr } => {
+                let value = expr.evaluate(symbols, location)?;
+                AssemblerInstruction::extract_operand(&Token::IntegerOperand { value }, location, results, symbols)?;
             }
 
             Token::LabelUsage { name } => {
-                if let Some(value) = symbols.symbol_value(name) {
-                    let byte1 = value;
-                    let byte2 = value >> 8;
-                    results.push(byte2 as u8);
-                    results.push(byte1 as u8);
-                }
+                let value = match symbols.symbol_value(name) {
+                    Some(value) => value,
+                    None => {
+                        return Err(AssemblerError::UnknownLabel {
+                            name: name.clone(),
+                            location: location.clone(),
+                        })
+                    }
+                };
+                let byte1 = value;
+                let byte2 = value >> 8;
+                results.push(byte2 as u8);
+                results.push(byte1 as u8);
             }
 
             _ => {
-                println!("Opcode found in operand field");
-                process::exit(1);
+                return Err(AssemblerError::NonOperandInOperandField {
+                    location: location.clone(),
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the expressions that can already be evaluated with their value.
+    /// The first pass does this as it goes, so a `load` of a large constant is sized right
+    /// and data directives know how much room their operands take.
+    /// Addresses computed from labels are kept, so an object file can relocate them
+    pub fn fold_expressions(&mut self, symbols: &SymbolTable) {
+        for (token, _) in self.operands_mut() {
+            let value = match token {
+                Token::Expression { expr } if !expr.uses_label() => expr.evaluate(symbols, &SourceLocation::default()).ok(),
+                _ => None,
+            };
+            if let Some(value) = value {
+                *token = Token::IntegerOperand { value };
             }
         }
     }
 
+    /// The operands that refer to labels, with the offset of their field in the encoded instruction
+    pub fn label_operands(&self) -> Vec<(usize, Expr, &SourceLocation)> {
+        let mut field = 1;
+        let mut found = vec![];
+        for (token, location) in self.operands() {
+            match token {
+                Token::LabelUsage { name } => found.push((field, Expr::Label(name.clone()), location)),
+                Token::Expression { expr } if expr.uses_label() => found.push((field, expr.clone(), location)),
+                _ => {}
+            }
+            // The same widths `extract_operand` encodes with
+            field += match token {
+                Token::Register { .. } => 1,
+                _ if field == INSTRUCTION_LENGTH as usize - 1 => 1,
+                _ => 2,
+            };
+        }
+        found
+    }
+
     /// Check it is label
     pub fn is_label(&self) -> bool {
         self.label.is_some()
@@ -106,14 +483,8 @@ impl AssemblerInstruction {
 
     pub fn get_directive_name(&self) -> Option<String> {
         match &self.directive {
-            Some(d) => {
-                match d {
-                    Token::Directive { name } => Some(name.to_string()),
-                    _ => None,
-                }
-            }
-
-            None => None,
+            Some(Token::Directive { name }) => Some(name.to_string()),
+            _ => None,
         }
     }
 
@@ -134,11 +505,8 @@ impl AssemblerInstruction {
     // Get the string constant from `.asciiz` directive
     pub fn get_string_constant(&self) -> Option<String> {
         match &self.operand1 {
-            Some(d) => match d {
-                Token::IrString { name } => Some(name.to_string()),
-                _ => None,
-            },
-            None => None,
+            Some(Token::IrString { name }) => Some(name.to_string()),
+            _ => None,
         }
     }
 }
@@ -146,20 +514,11 @@ impl AssemblerInstruction {
 
 named!(parse_instruction_combined<CompleteStr, AssemblerInstruction>,
     do_parse!(
-        label: opt!(parse_label_decl) >>
-        o: parse_opcode >>
-        operand1: opt!(parse_operand) >>
-        operand2: opt!(parse_operand) >>
-        operand3: opt!(parse_operand) >>
+        label: opt!(call!(with_location, parse_label_decl)) >>
+        o: call!(with_location, parse_opcode) >>
+        operands: many0!(call!(with_location, parse_operand)) >>
         (
-            AssemblerInstruction {
-                opcode: Some(o),
-                label,
-                directive: None,
-                operand1,
-                operand2,
-                operand3,
-            }
+            AssemblerInstruction::from_located_list(label, None, Some(o), operands)
         )
     )
 );
@@ -167,18 +526,19 @@ named!(parse_instruction_combined<CompleteStr, AssemblerInstruction>,
 /// Will try to parse out any of the Instruction forms
 named!(pub parse_instruction<CompleteStr, AssemblerInstruction>,
     do_parse!(
-        ins: alt!(
-            parse_instruction_combined
-        ) >>
+        ins: call!(with_location, parse_instruction_combined) >>
         (
-            ins
+            {
+                let (mut ins, location) = ins;
+                ins.locations.instruction = location;
+                ins
+            }
         )
     )
 );
 
 #[cfg(test)]
 mod tests {
-    use instruction::Opcode;
     use super::*;
 
     #[test]
@@ -192,9 +552,11 @@ mod tests {
                     opcode: Some(Token::Op { code: Opcode::LOAD }),
                     label: None,
                     directive: None,
-                    operand1: Some(Token::Register { reg_num: 0 }),
+                    operand1: Some(Token::Register { reg_num: 0, file: None }),
                     operand2: Some(Token::IntegerOperand { value: 100 }),
                     operand3: None,
+                    extra_operands: vec![],
+                    locations: InstructionLocations::default(),
                 }
             ))
         );
@@ -211,9 +573,11 @@ mod tests {
                     opcode: Some(Token::Op { code: Opcode::LOAD }),
                     label: None,
                     directive: None,
-                    operand1: Some(Token::Register { reg_num: 0 }),
+                    operand1: Some(Token::Register { reg_num: 0, file: None }),
                     operand2: Some(Token::LabelUsage { name: "test1".to_string() }),
                     operand3: None,
+                    extra_operands: vec![],
+                    locations: InstructionLocations::default(),
                 }
             ))
         );
@@ -233,6 +597,8 @@ mod tests {
                     operand1: None,
                     operand2: None,
                     operand3: None,
+                    extra_operands: vec![],
+                    locations: InstructionLocations::default(),
                 }
             ))
         );
@@ -249,9 +615,11 @@ mod tests {
                     opcode: Some(Token::Op { code: Opcode::ADD }),
                     label: None,
                     directive: None,
-                    operand1: Some(Token::Register { reg_num: 0 }),
-                    operand2: Some(Token::Register { reg_num: 1 }),
-                    operand3: Some(Token::Register { reg_num: 2 }),
+                    operand1: Some(Token::Register { reg_num: 0, file: None }),
+                    operand2: Some(Token::Register { reg_num: 1, file: None }),
+                    operand3: Some(Token::Register { reg_num: 2, file: None }),
+                    extra_operands: vec![],
+                    locations: InstructionLocations::default(),
                 }
             ))
         );
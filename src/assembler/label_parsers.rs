@@ -1,14 +1,18 @@
 use assembler::Token;
-use nom::{alphanumeric, multispace};
 use nom::types::CompleteStr;
 
-/// Look for a user-defined label, such as `label1:`
+/// Parser for the name of a label: letters, digits, `_` and `.`.
+/// A name starting with `.` is local to the label before it, one made of digits is a numeric label
+named!(pub parse_label_name<CompleteStr, CompleteStr>,
+    take_while1!(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+);
+
+/// Look for a user-defined label, such as `label1:`, `.loop:` or `1:`
 named!(pub parse_label_decl<CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
-            name: alphanumeric >>
+            name: parse_label_name >>
             tag!(":") >>
-            opt!(multispace) >>
             (
                 Token::LabelDeclaration{name: name.to_string()}
             )
@@ -18,11 +22,10 @@ named!(pub parse_label_decl<CompleteStr, Token>,
 
 /// Looks for a user-defined label which is used, such as `@label1`
 named!(pub parse_label_usage<CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
             tag!("@") >>
-            name: alphanumeric >>
-            opt!(multispace) >>
+            name: parse_label_name >>
             (
                 Token::LabelUsage{name: name.to_string()}
             )
@@ -32,7 +35,7 @@ named!(pub parse_label_usage<CompleteStr, Token>,
 
 #[cfg(test)]
 mod tests {
-    #![allow(unused_imports)]
+    #![allow(unused_imports, clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -44,6 +47,11 @@ mod tests {
         assert_eq!(token, Token::LabelDeclaration { name: "test".to_string() });
         let result = parse_label_decl(CompleteStr("test"));
         assert_eq!(result.is_ok(), false);
+
+        for name in &["my_label", ".loop", "main.loop", "1"] {
+            let (_, token) = parse_label_decl(CompleteStr(&format!("{}:", name))).unwrap();
+            assert_eq!(token, Token::LabelDeclaration { name: name.to_string() });
+        }
     }
 
     #[test]
@@ -54,5 +62,8 @@ mod tests {
         assert_eq!(token, Token::LabelUsage { name: "test".to_string() });
         let result = parse_label_usage(CompleteStr("test"));
         assert_eq!(result.is_ok(), false);
+
+        let (_, token) = parse_label_usage(CompleteStr("@1b")).unwrap();
+        assert_eq!(token, Token::LabelUsage { name: "1b".to_string() });
     }
 }
\ No newline at end of file
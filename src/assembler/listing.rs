@@ -0,0 +1,124 @@
+use assembler::source::SourceLocation;
+use assembler::symbols::{Symbol, SymbolTable, SymbolType};
+use linker::object::Section;
+
+/// How many bytes a row of the listing shows, one instruction
+const BYTES_PER_ROW: usize = 4;
+
+/// The bytes one source line was assembled to
+#[derive(Debug, Clone)]
+pub struct ListingLine {
+    pub section: Section,
+    /// Where the first byte is: an absolute address in the code, an offset in the read-only section,
+    /// the same values labels get
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    pub location: SourceLocation,
+    /// Shown after the source line, for bytes the line did not write itself, such as a pooled float constant
+    pub note: Option<String>,
+}
+
+/// Formats a listing with a row per source line: the section, the address, the bytes in hex,
+/// where the line is, the line itself and its note. Lines of more than 4 bytes continue on rows of their own
+pub fn render_listing(lines: &[ListingLine]) -> String {
+    let positions: Vec<String> =
+        lines.iter().map(|line| format!("{}:{}", line.location.file, line.location.line)).collect();
+    let width = positions.iter().map(String::len).max().unwrap_or(0);
+
+    let mut listing = String::new();
+    for (line, position) in lines.iter().zip(&positions) {
+        let section = match line.section {
+            Section::ReadOnly => "ro",
+            Section::Code => "code",
+        };
+        let mut rows = line.bytes.chunks(BYTES_PER_ROW);
+        let first = rows.next().unwrap_or(&[]);
+        let note = line.note.as_ref().map_or(String::new(), |note| format!("  ; {}", note));
+        listing.push_str(&format!(
+            "{:<4} {:#06x}  {:<11}  {:<width$}  {}{}\n",
+            section,
+            line.address,
+            hex(first),
+            position,
+            line.location.source_line.trim(),
+            note,
+            width = width
+        ));
+        for (i, row) in rows.enumerate() {
+            let address = line.address as usize + (i + 1) * BYTES_PER_ROW;
+            listing.push_str(&format!("{:<4} {:#06x}  {}\n", section, address, hex(row)));
+        }
+    }
+    listing
+}
+
+/// Formats a map of the symbols in the order they were declared: the name, the kind and the address
+pub fn render_symbol_map(symbols: &SymbolTable) -> String {
+    let width = symbols.iter().map(|symbol| symbol.name().len()).max().unwrap_or(0);
+    let mut map = String::new();
+    for symbol in symbols.iter() {
+        map.push_str(&format!(
+            "{:<width$}  {:<10}  {}\n",
+            symbol.name(),
+            symbol.symbol_type().to_string(),
+            symbol_address(symbol),
+            width = width
+        ));
+    }
+    map
+}
+
+/// The address of a label in hex, the value of a constant, or `-` if it is not known
+pub fn symbol_address(symbol: &Symbol) -> String {
+    let value = match symbol.symbol_type() {
+        SymbolType::Constant => symbol.value().map(|value| value.to_string()),
+        // Only the linker knows where an external symbol is
+        SymbolType::External => None,
+        SymbolType::CodeLabel | SymbolType::DataLabel => symbol.offset().map(|offset| format!("{:#06x}", offset)),
+    };
+    value.unwrap_or_else(|| "-".to_string())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+
+    #[test]
+    fn test_listing() {
+        let mut asm = Assembler::new();
+        asm.set_file_name("prog.iasm");
+        asm.enable_listing();
+        asm.assemble(".data\nmsg: .asciiz 'Hi'\nptr: .word @main\n.code\nmain: load $0 #100\nload $1 #70000\nloadf64 $2 #1.5\nloadf64 $3 #1.5\nhlt\n")
+            .unwrap();
+        assert_eq!(
+            asm.listing().unwrap(),
+            "ro   0x0000  48 69 00     prog.iasm:2  msg: .asciiz 'Hi'\n\
+             ro   0x0003  4f 00 00 00  prog.iasm:3  ptr: .word @main\n\
+             ro   0x0007  00 00 00 00  prog.iasm:7  loadf64 $2 #1.5  ; float constant 1.5\n\
+             ro   0x000b  00 00 f8 3f\n\
+             code 0x004f  00 00 00 64  prog.iasm:5  main: load $0 #100\n\
+             code 0x0053  00 01 11 70  prog.iasm:6  load $1 #70000\n\
+             code 0x0057  39 01 00 01\n\
+             code 0x005b  3a 02 00 07  prog.iasm:7  loadf64 $2 #1.5\n\
+             code 0x005f  3a 03 00 07  prog.iasm:8  loadf64 $3 #1.5\n\
+             code 0x0063  05 00 00 00  prog.iasm:9  hlt\n"
+        );
+    }
+
+    #[test]
+    fn test_symbol_map() {
+        let mut asm = Assembler::new();
+        assert_eq!(asm.listing(), None);
+        asm.assemble(".data\nmessage: .asciiz 'Hi'\n.equ SIZE #4\n.code\nmain: hlt\n").unwrap();
+        assert_eq!(
+            asm.symbol_map(),
+            "message  data label  0x0000\n\
+             SIZE     constant    4\n\
+             main     code label  0x0043\n"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use assembler::errors::AssemblerError;
+use assembler::program_parsers::Program;
+use assembler::source::SourceLocation;
+use assembler::Token;
+use std::collections::HashMap;
+
+/// Whether `name` is a numeric label such as `1`, which can be declared many times
+pub fn is_numeric_label(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Gives every local label the name the two passes know it by.
+/// `.loop` declared or used after `main:` becomes `main.loop`, and each declaration of a numeric
+/// label such as `1:` gets a name of its own, which `@1b` and `@1f` are resolved to
+pub fn resolve_local_labels(program: Program) -> Result<Program, Vec<AssemblerError>> {
+    let mut instructions = program.instructions;
+
+    // Where each numeric label is declared, in order
+    let mut numeric: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, inst) in instructions.iter().enumerate() {
+        if let Some(name) = inst.get_label_name() {
+            if is_numeric_label(&name) {
+                numeric.entry(name).or_default().push(index);
+            }
+        }
+    }
+
+    let mut errors = vec![];
+    // The last global label, which owns the `.name` labels after it
+    let mut scope: Option<String> = None;
+    for (index, inst) in instructions.iter_mut().enumerate() {
+        let label_location = inst.locations.label.clone();
+        if let Some(Token::LabelDeclaration { ref mut name }) = inst.label {
+            if is_numeric_label(name) {
+                *name = numeric_name(name, index);
+            } else if name.starts_with('.') {
+                match scope {
+                    Some(ref scope) => name.insert_str(0, scope),
+                    None => errors.push(no_scope(label_location)),
+                }
+            } else if label_location.expansion.is_none() {
+                // Labels of a macro's body, such as `over~1`, don't own the `.name` labels after the call
+                scope = Some(name.clone());
+            }
+        }
+
+        for (token, location) in inst.operands_mut() {
+            let mut resolve = |name: &mut String| match resolve(name, index, &scope, &numeric, location) {
+                Ok(Some(resolved)) => *name = resolved,
+                Ok(None) => {}
+                Err(e) => errors.push(e),
+            };
+            match token {
+                Token::LabelUsage { name } => resolve(name),
+                Token::Expression { expr } => expr.for_each_label(&mut resolve),
+                _ => {}
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Program { instructions })
+    } else {
+        Err(errors)
+    }
+}
+
+/// The name of the numeric label `digits` declared by the instruction at `index`
+fn numeric_name(digits: &str, index: usize) -> String {
+    format!("{}~{}", digits, index)
+}
+
+fn no_scope(location: SourceLocation) -> AssemblerError {
+    AssemblerError::InvalidLocalLabel { error: "a `.name` label needs a global label before it", location }
+}
+
+/// The full name of the label that `name`, used by the instruction at `index`, refers to,
+/// or `None` if `name` is a global label
+fn resolve(
+    name: &str,
+    index: usize,
+    scope: &Option<String>,
+    numeric: &HashMap<String, Vec<usize>>,
+    location: &SourceLocation,
+) -> Result<Option<String>, AssemblerError> {
+    if name.starts_with('.') {
+        return match scope {
+            Some(scope) => Ok(Some(format!("{}{}", scope, name))),
+            None => Err(no_scope(location.clone())),
+        };
+    }
+    if is_numeric_label(name) {
+        return Err(AssemblerError::InvalidLocalLabel {
+            error: "a numeric label is used with `b` or `f` after it, as in `@1b`",
+            location: location.clone(),
+        });
+    }
+
+    let (digits, forward) = match name.split_at(name.len() - 1) {
+        (digits, "b") if is_numeric_label(digits) => (digits, false),
+        (digits, "f") if is_numeric_label(digits) => (digits, true),
+        _ => return Ok(None),
+    };
+    // `1b` is the closest `1:` at or before the instruction, `1f` the closest one after it
+    let declarations = numeric.get(digits).map_or(&[][..], |d| d.as_slice());
+    let found = if forward {
+        declarations.iter().find(|&&declared| declared > index)
+    } else {
+        declarations.iter().rev().find(|&&declared| declared <= index)
+    };
+    match found {
+        Some(&declared) => Ok(Some(numeric_name(digits, declared))),
+        None => Err(AssemblerError::UnknownLabel { name: name.to_string(), location: location.clone() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::macros::expand_macros;
+    use assembler::program_parsers::parse_program;
+    use assembler::source::DEFAULT_FILE_NAME;
+
+    fn resolve_source(source: &str) -> Result<Program, Vec<AssemblerError>> {
+        resolve_local_labels(parse_program(source, DEFAULT_FILE_NAME).unwrap())
+    }
+
+    fn label_usage(program: &Program, index: usize) -> String {
+        match program.instructions[index].operands()[0].0 {
+            Token::LabelUsage { name } => name.clone(),
+            token => panic!("expected a label, found {:?}", token),
+        }
+    }
+
+    #[test]
+    fn test_scoped_labels() {
+        let program = resolve_source(
+            ".code\nmain: jmp @.loop\n.loop: jmp @.loop\nother: jmp @.loop\n.loop: jmp @main.loop\n",
+        )
+        .unwrap();
+        assert_eq!(program.instructions[2].get_label_name(), Some("main.loop".to_string()));
+        assert_eq!(program.instructions[4].get_label_name(), Some("other.loop".to_string()));
+        assert_eq!(label_usage(&program, 1), "main.loop");
+        assert_eq!(label_usage(&program, 2), "main.loop");
+        assert_eq!(label_usage(&program, 3), "other.loop");
+        assert_eq!(label_usage(&program, 4), "main.loop");
+    }
+
+    #[test]
+    fn test_numeric_labels() {
+        let program = resolve_source(".code\n1: jmp @1f\n1: jmp @1b\njmp @1b\n1: jmp @1b\n").unwrap();
+        assert_eq!(program.instructions[1].get_label_name(), Some("1~1".to_string()));
+        assert_eq!(label_usage(&program, 1), "1~2");
+        assert_eq!(label_usage(&program, 2), "1~2");
+        assert_eq!(label_usage(&program, 3), "1~2");
+        assert_eq!(label_usage(&program, 4), "1~4");
+        // Numeric labels don't start a scope
+        let program = resolve_source(".code\nmain: hlt\n1: jmp @.end\n.end: hlt\n").unwrap();
+        assert_eq!(label_usage(&program, 2), "main.end");
+    }
+
+    #[test]
+    fn test_scope_across_macro_labels() {
+        let program = expand_macros(
+            parse_program(
+                ".macro skip_inc \\reg\njmp @over\ninc \\reg\nover: inc \\reg\n.endm\n\
+                 .code\nmain: jmp @.done\nskip_inc $1\n.done: hlt\n",
+                DEFAULT_FILE_NAME,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let program = resolve_local_labels(program).unwrap();
+        assert_eq!(label_usage(&program, 1), "main.done");
+        assert_eq!(program.instructions[5].get_label_name(), Some("main.done".to_string()));
+    }
+
+    #[test]
+    fn test_local_label_errors() {
+        let errors = resolve_source(".code\n.loop: jmp @1f\njmp @2b\njmp @1\n1: jmp @.loop\n").unwrap_err();
+        assert_eq!(errors.len(), 4);
+        assert_eq!(errors[0].to_string(), "Invalid local label: a `.name` label needs a global label before it");
+        assert_eq!((errors[0].location().line, errors[0].location().column), (2, 1));
+        assert_eq!(errors[1].to_string(), "Label @2b was used but never declared");
+        assert_eq!(
+            errors[2].to_string(),
+            "Invalid local label: a numeric label is used with `b` or `f` after it, as in `@1b`"
+        );
+        assert_eq!((errors[3].location().line, errors[3].location().column), (5, 8));
+    }
+}
@@ -0,0 +1,309 @@
+use assembler::errors::AssemblerError;
+use assembler::instruction_parsers::AssemblerInstruction;
+use assembler::local_labels::is_numeric_label;
+use assembler::program_parsers::Program;
+use assembler::pseudo::is_pseudo_instruction;
+use assembler::source::{MacroExpansion, SourceLocation};
+use assembler::Token;
+use instruction::Opcode;
+use std::collections::HashMap;
+
+/// How deep macros may call other macros, so a macro that calls itself is an error instead of a hang
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// A macro defined with `.macro name \param ...` and closed by `.endm`
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<AssemblerInstruction>,
+    /// Labels declared in the body, renamed in every expansion so each one gets its own
+    labels: Vec<String>,
+}
+
+/// A macro whose body is still being read
+#[derive(Debug)]
+struct Definition {
+    name: String,
+    name_location: SourceLocation,
+    location: SourceLocation,
+    mac: Macro,
+}
+
+#[derive(Debug, Default)]
+struct Expander {
+    macros: HashMap<String, Macro>,
+    /// How many expansions there were so far, which makes the local labels of each one unique
+    expansions: usize,
+    errors: Vec<AssemblerError>,
+}
+
+/// Replaces every call of a macro with the macro's body.
+/// The definitions are taken out, so the two passes never see them
+pub fn expand_macros(program: Program) -> Result<Program, Vec<AssemblerError>> {
+    let mut expander = Expander::default();
+    let rest = expander.collect_definitions(program.instructions);
+    let mut instructions = vec![];
+    for inst in rest {
+        expander.expand(inst, 0, &mut instructions);
+    }
+
+    if expander.errors.is_empty() {
+        Ok(Program { instructions })
+    } else {
+        Err(expander.errors)
+    }
+}
+
+impl Expander {
+    /// Takes the macro definitions out of `instructions`, returning everything else.
+    /// Macros can be called before they are defined
+    fn collect_definitions(&mut self, instructions: Vec<AssemblerInstruction>) -> Vec<AssemblerInstruction> {
+        let mut rest = vec![];
+        let mut current: Option<Definition> = None;
+        for inst in instructions {
+            let directive = inst.get_directive_name();
+            match (current.is_some(), directive.as_deref()) {
+                (false, Some("macro")) => current = self.start_definition(&inst),
+                (true, Some("macro")) => self.invalid(&inst, "a macro can't be defined inside another one"),
+                (true, Some("endm")) => {
+                    if let Some(definition) = current.take() {
+                        self.finish_definition(definition);
+                    }
+                }
+                (false, Some("endm")) => self.invalid(&inst, "`.endm` without a `.macro`"),
+                (true, _) => {
+                    if let Some(ref mut definition) = current {
+                        // Numeric labels are told apart by position, so they need no renaming
+                        match inst.get_label_name() {
+                            Some(ref name) if is_numeric_label(name) => {}
+                            Some(name) => definition.mac.labels.push(name),
+                            None => {}
+                        }
+                        definition.mac.body.push(inst);
+                    }
+                }
+                (false, _) => rest.push(inst),
+            }
+        }
+
+        if let Some(definition) = current {
+            self.errors.push(AssemblerError::InvalidMacro {
+                error: "`.macro` is never closed by `.endm`",
+                location: definition.location,
+            });
+        }
+        rest
+    }
+
+    /// Reads the name and the parameters in `.macro name \param ...`
+    fn start_definition(&mut self, inst: &AssemblerInstruction) -> Option<Definition> {
+        let operands = inst.operands();
+        let (name, name_location) = match operands.first() {
+            Some((Token::Name { name }, location)) => (name.clone(), (*location).clone()),
+            _ => {
+                self.errors.push(AssemblerError::WrongDirectiveOperand {
+                    directive: "macro".to_string(),
+                    expected: "a name followed by parameters such as \\reg",
+                    location: inst.locations.directive.clone(),
+                });
+                return None;
+            }
+        };
+
+        let mut params = vec![];
+        for (token, location) in &operands[1..] {
+            match token {
+                Token::MacroParameter { name } => params.push(name.clone()),
+                _ => self.errors.push(AssemblerError::WrongDirectiveOperand {
+                    directive: "macro".to_string(),
+                    expected: "a name followed by parameters such as \\reg",
+                    location: (*location).clone(),
+                }),
+            }
+        }
+
+        Some(Definition {
+            name,
+            name_location,
+            location: inst.locations.directive.clone(),
+            mac: Macro { params, body: vec![], labels: vec![] },
+        })
+    }
+
+    fn finish_definition(&mut self, definition: Definition) {
+        // A call of a macro named like an opcode would be parsed as the opcode
+        if self.macros.contains_key(&definition.name)
+            || Opcode::from_mnemonic(&definition.name).is_some()
+            || is_pseudo_instruction(&definition.name)
+        {
+            self.errors.push(AssemblerError::SymbolAlreadyDeclared {
+                name: definition.name,
+                location: definition.name_location,
+            });
+            return;
+        }
+        self.macros.insert(definition.name, definition.mac);
+    }
+
+    /// Appends `inst` to `out`, or the expansion of the macro it calls
+    fn expand(&mut self, inst: AssemblerInstruction, depth: usize, out: &mut Vec<AssemblerInstruction>) {
+        let name = match inst.opcode {
+            Some(Token::UnknownOp { ref name }) if self.macros.contains_key(name) => name.clone(),
+            _ => {
+                // Parameters only mean something in the body of a macro
+                for (token, location) in inst.operands() {
+                    if let Token::MacroParameter { name } = token {
+                        self.errors.push(AssemblerError::UnknownMacroParameter {
+                            name: name.clone(),
+                            location: location.clone(),
+                        });
+                    }
+                }
+                out.push(inst);
+                return;
+            }
+        };
+
+        if depth >= MAX_EXPANSION_DEPTH {
+            self.invalid(&inst, "macros call each other too deeply, a macro may be calling itself");
+            return;
+        }
+
+        let expanded = match self.substitute(&name, &inst) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                self.errors.push(e);
+                return;
+            }
+        };
+
+        // A label on the call names the first instruction of the expansion
+        if let Some(label) = inst.label.clone() {
+            let mut label_only =
+                AssemblerInstruction::from_located(Some((label, inst.locations.label.clone())), None, None, [None, None, None]);
+            label_only.locations.instruction = inst.locations.instruction.clone();
+            out.push(label_only);
+        }
+        for body_inst in expanded {
+            self.expand(body_inst, depth + 1, out);
+        }
+    }
+
+    /// Copies the body of macro `name` for the call `call`, with the arguments in place of
+    /// the parameters and the local labels renamed
+    fn substitute(&mut self, name: &str, call: &AssemblerInstruction) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+        let mac = &self.macros[name];
+        let args = call.operands();
+        if args.len() != mac.params.len() {
+            return Err(AssemblerError::WrongMacroArgumentCount {
+                name: name.to_string(),
+                expected: mac.params.len(),
+                found: args.len(),
+                location: call.locations.instruction.clone(),
+            });
+        }
+
+        self.expansions += 1;
+        let suffix = format!("~{}", self.expansions);
+        let rename = |label: &mut String| {
+            if mac.labels.contains(label) {
+                label.push_str(&suffix);
+            }
+        };
+        let expansion = MacroExpansion { name: name.to_string(), call: call.locations.instruction.clone() };
+
+        let mut expanded = vec![];
+        for body_inst in &mac.body {
+            let mut inst = body_inst.clone();
+            for location in inst.locations.iter_mut() {
+                location.expansion = Some(Box::new(expansion.clone()));
+            }
+            if let Some(Token::LabelDeclaration { ref mut name }) = inst.label {
+                rename(name);
+            }
+
+            for (token, location) in inst.operands_mut() {
+                if let Token::MacroParameter { name } = token.clone() {
+                    let index = match mac.params.iter().position(|param| *param == name) {
+                        Some(index) => index,
+                        None => return Err(AssemblerError::UnknownMacroParameter { name, location: location.clone() }),
+                    };
+                    *token = args[index].0.clone();
+                    *location = args[index].1.clone();
+                    continue;
+                }
+                match token {
+                    Token::LabelUsage { name } => rename(name),
+                    Token::Expression { expr } => expr.for_each_label(&mut |name| rename(name)),
+                    _ => {}
+                }
+            }
+            expanded.push(inst);
+        }
+        Ok(expanded)
+    }
+
+    fn invalid(&mut self, inst: &AssemblerInstruction, error: &'static str) {
+        self.errors.push(AssemblerError::InvalidMacro { error, location: inst.locations.instruction.clone() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::bool_assert_comparison)]
+
+    use super::*;
+    use assembler::program_parsers::parse_program;
+    use assembler::source::DEFAULT_FILE_NAME;
+
+    fn expand(source: &str) -> Result<Program, Vec<AssemblerError>> {
+        expand_macros(parse_program(source, DEFAULT_FILE_NAME).unwrap())
+    }
+
+    #[test]
+    fn test_expand_macro() {
+        let program = expand(
+            ".macro countdown \\reg \\from\nload \\reg \\from\nloop: dec \\reg\njmpe @loop\n.endm\n\
+             countdown $1 #3\nstart: countdown $2 #5\n",
+        )
+        .unwrap();
+        assert_eq!(program.instructions.len(), 7);
+        assert_eq!(program.instructions[3].get_label_name(), Some("start".to_string()));
+        assert_eq!(program.instructions[3].is_opcode(), false);
+        assert_eq!(program.instructions[1].get_label_name(), Some("loop~1".to_string()));
+        assert_eq!(program.instructions[6].operand1, Some(Token::LabelUsage { name: "loop~2".to_string() }));
+    }
+
+    #[test]
+    fn test_expansion_locations() {
+        let program = expand(".macro twice \\r\ninc \\r\ninc \\r\n.endm\ntwice $3\n").unwrap();
+        let locations = &program.instructions[0].locations;
+        assert_eq!(locations.opcode.line, 2);
+        assert_eq!(locations.operands[0].line, 5);
+        let expansion = locations.opcode.expansion.as_ref().unwrap();
+        assert_eq!(expansion.name, "twice");
+        assert_eq!((expansion.call.line, expansion.call.column), (5, 1));
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        let errors = expand(".macro one \\a\ninc \\b\n.endm\none $1\none $1 $2\ninc \\c\n.endm\n.macro open\n").unwrap_err();
+        assert_eq!(errors.len(), 5);
+        assert_eq!(errors[0].to_string(), "Invalid macro: `.endm` without a `.macro`");
+        assert_eq!(errors[1].to_string(), "Invalid macro: `.macro` is never closed by `.endm`");
+        assert_eq!(errors[2].to_string(), "\\b is not a parameter of the macro it is used in");
+        assert_eq!(errors[2].location().line, 2);
+        assert_eq!(errors[3].to_string(), "Macro `one` expects 1 argument(s), found 2");
+        assert_eq!(errors[4].to_string(), "\\c is not a parameter of the macro it is used in");
+
+        let errors = expand(".macro inc\n.endm\n.macro twice\n.endm\n.macro twice\n.endm\n.macro beq\n.endm\n").unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!((errors[0].location().line, errors[0].location().column), (1, 8));
+        assert_eq!((errors[1].location().line, errors[1].location().column), (5, 8));
+        assert_eq!(errors[2].to_string(), "Symbol beq was previously declared.");
+
+        let errors = expand(".macro forever\nforever\n.endm\nforever\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location().expansion.is_some(), true);
+    }
+}
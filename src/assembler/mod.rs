@@ -1,35 +1,100 @@
 use assembler::errors::AssemblerError;
+use assembler::expressions::Expr;
 use assembler::instruction_parsers::AssemblerInstruction;
+use assembler::includes::resolve_includes;
+use assembler::listing::{render_listing, render_symbol_map, ListingLine};
+use assembler::local_labels::resolve_local_labels;
+use assembler::macros::expand_macros;
 use assembler::program_parsers::*;
+use assembler::pseudo::expand_pseudo_instruction;
+use assembler::register_aliases::resolve_register_aliases;
+use assembler::register_parsers::RegisterFile;
+use assembler::source::{SourceLocation, DEFAULT_FILE_NAME};
 use assembler::symbols::*;
 use byteorder::{LittleEndian, WriteBytesExt};
 use instruction::Opcode;
-use nom::types::CompleteStr;
+use linker::object::{ExportedSymbol, Field, ObjectFile, Relocation, RelocationTarget, Section};
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
+/// Like nom's `ws!`, but comments count as whitespace in front of every parser it wraps.
+/// Only plain whitespace is eaten after the last one, so a match never ends in a comment
+/// and the span recorded for a token stays on the token
+macro_rules! ws_comments (
+    ($i:expr, $($args:tt)*) => (
+        {
+            use nom::multispace0;
+            use nom::Err;
+            use nom::Convert;
+
+            match sep!($i, $crate::assembler::comment_parsers::space, $($args)*) {
+                Err(e) => Err(e),
+                Ok((i1, o)) => match multispace0(i1) {
+                    Err(e) => Err(Err::convert(e)),
+                    Ok((i2, _)) => Ok((i2, o)),
+                },
+            }
+        }
+    )
+);
+
+// The parsers declared with `named!` keep their `///` comments, which rustdoc doesn't attach to macro invocations
+#[allow(unused_doc_comments)]
+pub mod comment_parsers;
+#[allow(unused_doc_comments)]
 pub mod opcode_parsers;
+#[allow(unused_doc_comments)]
 pub mod register_parsers;
+#[allow(unused_doc_comments)]
 pub mod operand_parsers;
+#[allow(unused_doc_comments)]
 pub mod instruction_parsers;
+#[allow(unused_doc_comments)]
 pub mod program_parsers;
+#[allow(unused_doc_comments)]
 pub mod directive_parsers;
+#[allow(unused_doc_comments)]
 pub mod label_parsers;
 pub mod symbols;
 pub mod errors;
+pub mod source;
+pub mod expressions;
+pub mod macros;
+pub mod includes;
+pub mod listing;
+pub mod local_labels;
+pub mod pseudo;
+pub mod register_aliases;
 
 pub const PIE_HEADER_PREFIX: [u8; 4] = [0x45, 0x50, 0x49, 0x45];
 pub const PIE_HEADER_LENGTH: usize = 64;
+/// Every instruction is encoded as an opcode followed by three bytes of operands
+pub const INSTRUCTION_LENGTH: u32 = 4;
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Op { code: Opcode },
-    Register { reg_num: u8 },
-    IntegerOperand { value: i32 },
+    /// A word in the opcode field that is not a known mnemonic
+    UnknownOp { name: String },
+    /// A register, with the registers its name belongs to, or `None` for a number such as `$3`, which fits both
+    Register { reg_num: u8, file: Option<RegisterFile> },
+    IntegerOperand { value: i64 },
     FloatOperand { value: f64 },
     LabelDeclaration { name: String },
     LabelUsage { name: String },
     Directive { name: String },
     IrString { name: String },
+    /// An integer operand computed from constants and labels, such as `#SIZE*2` or `@table+4`
+    Expression { expr: Expr },
+    /// The bare name after `.equ`, `.const` and `.macro`
+    Name { name: String },
+    /// A parameter of the macro being defined, `\name`
+    MacroParameter { name: String },
+    /// A register named by an alias declared with `.reg`, such as `$count`
+    RegisterAlias { name: String },
 }
 
 #[derive(Debug, Default)]
@@ -44,14 +109,28 @@ pub struct Assembler {
     pub bytecode: Vec<u8>,
     /// Tracks the current offset of the read-only section
     ro_offset: u32,
+    /// Tracks the current offset of the code section, relative to its start
+    code_offset: u32,
     /// A list of all the sections we've seen in the code
     sections: Vec<AssemblerSection>,
     /// The current section the assembler is in
     current_section: Option<AssemblerSection>,
-    /// The current instruction the assembler is converting to bytecode
-    current_instruction: u32,
     /// Any errors we find along the way. At the end, we'll present them to the user
     errors: Vec<AssemblerError>,
+    /// Data values that use labels, written at the end of the first pass
+    data_fixups: Vec<DataFixup>,
+    /// The file the source came from, used when reporting errors and to find included files
+    file: Option<String>,
+    /// Directories searched for included files that are not next to the file including them
+    include_dirs: Vec<PathBuf>,
+    /// Whether references to labels are recorded as relocations, when assembling an object file
+    relocatable: bool,
+    /// The fields the linker writes once it knows where the sections go
+    relocations: Vec<Relocation>,
+    /// The labels declared with `.global`, and where
+    exports: Vec<(String, SourceLocation)>,
+    /// The bytes each source line was assembled to, if a listing was asked for
+    listing: Option<Vec<ListingLine>>,
 }
 
 pub type AssemblerResult = Result<Vec<u8>, Vec<AssemblerError>>;
@@ -65,19 +144,53 @@ impl Assembler {
             ro: Vec::new(),
             bytecode: Vec::new(),
             ro_offset: 0,
+            code_offset: 0,
             sections: Vec::new(),
             current_section: None,
-            current_instruction: 0,
             errors: Vec::new(),
+            data_fixups: Vec::new(),
+            file: None,
+            include_dirs: Vec::new(),
+            relocatable: false,
+            relocations: Vec::new(),
+            exports: Vec::new(),
+            listing: None,
         }
     }
 
+    /// Sets the file name errors are reported against
+    pub fn set_file_name(&mut self, file: &str) {
+        self.file = Some(file.to_string());
+    }
+
+    /// Records the bytes of every source line while assembling, for `listing`
+    pub fn enable_listing(&mut self) {
+        self.listing = Some(Vec::new());
+    }
+
+    /// The listing of the last assembly, if `enable_listing` was called before it
+    pub fn listing(&self) -> Option<String> {
+        self.listing.as_ref().map(|lines| render_listing(lines))
+    }
+
+    /// The name, kind and address of every symbol, in the order they were declared
+    pub fn symbol_map(&self) -> String {
+        render_symbol_map(&self.symbols)
+    }
+
+    /// Adds a directory to search for `.include`d files, after the directory of the including file
+    pub fn add_include_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+    }
+
+    fn file_name(&self) -> String {
+        self.file.clone().unwrap_or_else(|| DEFAULT_FILE_NAME.to_string())
+    }
+
     /// write a fixed header to instructions vec
     fn write_pie_header(&self) -> Vec<u8> {
         let mut header = vec![];
-        for byte in PIE_HEADER_PREFIX.iter() {
-            header.push(byte.clone());
-        }
+        header.extend_from_slice(&PIE_HEADER_PREFIX);
         // Now we need to calculate the starting offset so that the
         // VM knows where the `read-only section` ends
         let mut wtr: Vec<u8> = vec![];
@@ -90,60 +203,151 @@ impl Assembler {
         header.append(&mut wtr);
 
         while header.len() < PIE_HEADER_LENGTH {
-            header.push(0 as u8);
+            header.push(0);
         }
 
         header
     }
 
-    /// assemble asm to instructions
-    pub fn assemble(&mut self, raw: &str) -> AssemblerResult {
-        match parse_program(CompleteStr(raw)) {
-            Ok((_remainder, program)) => {
-                // todo: add a check for `remainder` which should be ""
-
-                // Start processing the AssembledInstructions. This is the first pass of our two-pass assembler.
-                // We pass a read-only reference down to another function.
-                self.process_first_phase(&program);
-                if !self.errors.is_empty() {
-                    return Err(self.errors.clone());
-                }
+    /// Reads the file at `path` and assembles it, resolving its includes relative to it
+    pub fn assemble_file<P: AsRef<Path>>(&mut self, path: P) -> AssemblerResult {
+        let source = self.read_source(path.as_ref())?;
+        self.assemble(&source)
+    }
+
+    /// Reads the file at `path` and assembles it into an object file
+    pub fn assemble_object_file<P: AsRef<Path>>(&mut self, path: P) -> Result<ObjectFile, Vec<AssemblerError>> {
+        let source = self.read_source(path.as_ref())?;
+        self.assemble_object(&source)
+    }
 
-                // Make sure that we have at least one data section and one code section
-                if self.sections.len() != 2 {
-                    // todo: detail out which ones are missing
-                    println!("Did not find at least two sections");
+    /// Reads the source at `path`, which errors are then reported against
+    fn read_source(&mut self, path: &Path) -> Result<String, Vec<AssemblerError>> {
+        let name = path.display().to_string();
+        self.set_file_name(&name);
+        fs::read_to_string(path).map_err(|e| {
+            let location = SourceLocation { file: Rc::from(name.as_str()), line: 1, column: 1, ..Default::default() };
+            vec![AssemblerError::UnreadableFile { path: name, error: e.kind(), location }]
+        })
+    }
 
-                    self.errors.push(AssemblerError::InsufficientSections);
-                    return Err(self.errors.clone());
-                }
+    /// assemble asm to instructions.
+    /// Includes are resolved relative to the file set with `set_file_name`, or the current directory
+    pub fn assemble(&mut self, raw: &str) -> AssemblerResult {
+        let mut body = self.assemble_sections(raw)?;
+        // write header after second pass, followed by the read-only section and the code
+        let mut assembled_program = self.write_pie_header();
+        assembled_program.extend_from_slice(&self.ro);
+        assembled_program.append(&mut body);
+        Ok(assembled_program)
+    }
 
-                // second pass which translates opcodes and operands into the bytecode
-                let mut body = self.process_second_phase(&program);
-                // write header after second pass
-                let mut assembled_program = self.write_pie_header();
-                assembled_program.append(&mut body);
-                Ok(assembled_program)
-            },
+    /// Assembles a module into an object file, which `linker::link` combines with others.
+    /// References to labels become relocations, since only the linker knows where the sections go
+    pub fn assemble_object(&mut self, raw: &str) -> Result<ObjectFile, Vec<AssemblerError>> {
+        self.relocatable = true;
+        let code = self.assemble_sections(raw)?;
 
-            Err(e) => {
-                println!("There was an error assembling the code: {:?}", e);
-                Err(vec![AssemblerError::ParseError { error: e.to_string() }])
+        let code_start = self.code_start();
+        let mut exports: Vec<ExportedSymbol> = vec![];
+        for (name, _) in &self.exports {
+            if exports.iter().any(|symbol| symbol.name == *name) {
+                continue;
             }
+            if let (Some(section), Some(value)) = (self.symbols.symbol_section(name), self.symbols.symbol_value(name)) {
+                let offset = match section {
+                    Section::ReadOnly => value,
+                    Section::Code => value - code_start,
+                };
+                exports.push(ExportedSymbol { name: name.clone(), section, offset });
+            }
+        }
+        let imports = self
+            .symbols
+            .iter()
+            .filter(|symbol| *symbol.symbol_type() == SymbolType::External)
+            .map(|symbol| symbol.name().to_string())
+            .collect();
+
+        Ok(ObjectFile {
+            name: self.file_name(),
+            ro_data: self.ro.clone(),
+            code,
+            exports,
+            imports,
+            relocations: mem::take(&mut self.relocations),
+        })
+    }
+
+    /// Runs both passes, filling the read-only section and returning the code
+    fn assemble_sections(&mut self, raw: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        let program = parse_program(raw, &self.file_name())?;
+        let program = resolve_includes(program, self.file.as_ref().map(Path::new), &self.include_dirs)?;
+        // Macros are expanded next, so the two passes only see the instructions they stand for
+        let program = expand_macros(program)?;
+        // Then local labels get the names the passes know them by, and register aliases their registers
+        let program = resolve_local_labels(program)?;
+        let mut program = resolve_register_aliases(program)?;
+
+        // Start processing the AssembledInstructions. This is the first pass of our two-pass assembler.
+        // It only changes the program to fold the expressions it can evaluate.
+        self.process_first_phase(&mut program);
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
+        }
+
+        // Make sure that we have at least one data section and one code section
+        let has_data = self.sections.iter().any(|s| matches!(s, AssemblerSection::Data { .. }));
+        let has_code = self.sections.iter().any(|s| matches!(s, AssemblerSection::Code { .. }));
+        if !has_data || !has_code {
+            let mut location = SourceLocation::between(raw, raw);
+            location.resolve(raw, &self.file_name());
+            self.errors.push(AssemblerError::InsufficientSections { location });
+            return Err(self.errors.clone());
+        }
+
+        // second pass which translates opcodes and operands into the bytecode
+        let body = self.process_second_phase(&program);
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
         }
+        Ok(body)
+    }
+
+    /// The address of the first instruction: the code comes after the header and the read-only section
+    fn code_start(&self) -> u32 {
+        (PIE_HEADER_LENGTH + self.ro.len()) as u32
     }
 
     /// Runs the first pass assembling process.
     /// It looks for labels and puts them in the symbol table
-    fn process_first_phase(&mut self, p: &Program) {
-        for inst in &p.instructions {
+    fn process_first_phase(&mut self, p: &mut Program) {
+        // Pseudo-instructions are replaced first, so code labels count the real instructions they stand for
+        for inst in mem::take(&mut p.instructions) {
+            match expand_pseudo_instruction(inst) {
+                Ok(mut real) => p.instructions.append(&mut real),
+                Err(e) => self.errors.push(e),
+            }
+        }
+
+        // Code labels only get their final address once the size of the read-only section is known
+        let mut code_labels = vec![];
+        for (index, inst) in p.instructions.iter_mut().enumerate() {
+            inst.fold_expressions(&self.symbols);
+
             // deal with label
             if inst.is_label() {
                 if self.current_section.is_some() {
-                    self.process_label_decl(&inst);
+                    self.process_label_decl(inst);
+                    // Labels left alone by a macro call name the code that follows, like the ones on opcodes
+                    if !inst.is_directive() {
+                        if let Some(name) = inst.get_label_name() {
+                            code_labels.push((name, index));
+                        }
+                    }
                 } else {
                     self.errors.push(AssemblerError::NoSegmentDeclarationFound {
-                        instruction: self.current_instruction,
+                        location: inst.locations.instruction.clone(),
                     });
                 }
             }
@@ -152,7 +356,33 @@ impl Assembler {
             if inst.is_directive() {
                 self.process_directive(inst);
             }
-            self.current_instruction += 1;
+        }
+
+        // Constants can be used before they are declared, and the size of an instruction depends on its
+        // immediates, so instructions are only sized once every constant has its value
+        let mut offsets = Vec::with_capacity(p.instructions.len());
+        for inst in p.instructions.iter_mut() {
+            inst.fold_expressions(&self.symbols);
+            if let Some((_, value)) = inst.float_constant() {
+                self.pool_float_constant(value, &inst.locations.operands[1]);
+            }
+            offsets.push(self.code_offset);
+            self.code_offset += inst.size();
+        }
+
+        // Code labels are absolute addresses
+        let code_start = self.code_start();
+        for (name, index) in code_labels {
+            self.symbols.set_symbol_offset(&name, code_start + offsets[index]);
+        }
+        self.apply_data_fixups();
+        self.check_exports();
+        // Fixups changed data the listing recorded
+        if let Some(ref mut lines) = self.listing {
+            for line in lines.iter_mut() {
+                let (start, end) = (line.address as usize, line.address as usize + line.bytes.len());
+                line.bytes.copy_from_slice(&self.ro[start..end]);
+            }
         }
 
         // Once we're done with this function, set the phase to second
@@ -166,29 +396,49 @@ impl Assembler {
             Some(name) => name,
             None => {
                 self.errors.push(AssemblerError::StringConstantDeclaredWithoutLabel {
-                    instruction: self.current_instruction,
+                    location: inst.locations.instruction.clone(),
                 });
                 return;
             }
         };
 
         if self.symbols.has_symbol(&name) {
-            self.errors.push(AssemblerError::SymbolAlreadyDeclared);
+            self.errors.push(AssemblerError::SymbolAlreadyDeclared {
+                name,
+                location: inst.locations.label.clone(),
+            });
             return;
         }
 
-        let symbol = Symbol::new(name, SymbolType::Label);
-        self.symbols.add_symbol(symbol);
+        // Labels on data directives name read-only offsets, the others name code
+        let symbol_type = if inst.is_directive() { SymbolType::DataLabel } else { SymbolType::CodeLabel };
+        self.symbols.add_symbol(Symbol::new(name, symbol_type).with_location(inst.locations.label.clone()));
     }
 
     /// Runs the second pass of the assembler
     fn process_second_phase(&mut self, p: &Program) -> Vec<u8> {
-        self.current_instruction = 0;
+        let code_start = self.code_start();
         let mut program = vec![];
         for inst in &p.instructions {
             if inst.is_opcode() {
-                let mut bytes = inst.to_bytes(&self.symbols);
-                program.append(&mut bytes);
+                match inst.to_bytes(&self.symbols) {
+                    Ok(mut bytes) => {
+                        if self.relocatable {
+                            self.relocate_instruction(inst, program.len() as u32);
+                        }
+                        if let Some(ref mut lines) = self.listing {
+                            lines.push(ListingLine {
+                                section: Section::Code,
+                                address: code_start + program.len() as u32,
+                                bytes: bytes.clone(),
+                                location: inst.locations.opcode.clone(),
+                                note: None,
+                            });
+                        }
+                        program.append(&mut bytes)
+                    }
+                    Err(e) => self.errors.push(e),
+                }
             }
             if inst.is_directive() {
                 self.process_directive(inst);
@@ -202,91 +452,421 @@ impl Assembler {
         let directive_name = match inst.get_directive_name() {
             Some(name) => name,
             None => {
-                println!("Directive has an invalid name: {:?}", inst);
+                self.errors.push(AssemblerError::UnknownDirectiveFound {
+                    directive: format!("{:?}", inst.directive),
+                    location: inst.locations.directive.clone(),
+                });
                 return;
             }
         };
 
-        // check if there were any operands.
-        if inst.has_operands() {
-            match directive_name.as_ref() {
-                "asciiz" => self.handle_asciiz(inst),
-                _ => {
-                    self.errors.push(AssemblerError::UnknownDirectiveFound {
-                        directive: directive_name.clone(),
-                    });
-                    return;
-                }
+        match directive_name.as_ref() {
+            "asciiz" | "ascii" | "byte" | "half" | "word" | "float" | "space" | "align" => {
+                self.handle_data(inst, &directive_name)
+            }
+            "equ" | "const" => self.handle_constant(inst, &directive_name),
+            "global" | "extern" => self.handle_linkage(inst, &directive_name),
+            // Section headers such as `.code` are the only directives without operands
+            _ if !inst.has_operands() => self.process_section_header(inst, &directive_name),
+            _ => {
+                self.errors.push(AssemblerError::UnknownDirectiveFound {
+                    directive: directive_name.clone(),
+                    location: inst.locations.directive.clone(),
+                });
             }
-        } else {
-            self.process_section_header(&directive_name);
         }
     }
 
     /// Handles a declaration of a section header, such as:
     /// .code
-    fn process_section_header(&mut self, header_name: &str) {
+    fn process_section_header(&mut self, inst: &AssemblerInstruction, header_name: &str) {
         let new_section: AssemblerSection = header_name.into();
 
         if new_section == AssemblerSection::Unknown {
-            println!("Found an section header that is unknown: {:#?}", header_name);
+            self.errors.push(AssemblerError::UnknownDirectiveFound {
+                directive: header_name.to_string(),
+                location: inst.locations.directive.clone(),
+            });
             return;
         }
 
-        // TODO: Check if we really need to keep a list of all sections seen
-        self.sections.push(new_section.clone());
+        // A section can be declared again, by an included file for instance, and what follows
+        // is added to the one seen before
+        if !self.sections.contains(&new_section) {
+            self.sections.push(new_section.clone());
+        }
         self.current_section = Some(new_section);
     }
 
-    /// Handles a declaration of a null-terminated string:
+    /// Stores a float constant used as an immediate in the read-only section, once per distinct value.
+    /// The listing shows it on a row of its own, at the first line that uses it
+    fn pool_float_constant(&mut self, value: f64, location: &SourceLocation) {
+        if self.symbols.float_constant_offset(value).is_some() {
+            return;
+        }
+        self.symbols.add_float_constant(value, self.ro_offset);
+        let start = self.ro.len();
+        self.ro.write_f64::<LittleEndian>(value).unwrap();
+        if let Some(ref mut lines) = self.listing {
+            lines.push(ListingLine {
+                section: Section::ReadOnly,
+                address: self.ro_offset,
+                bytes: self.ro[start..].to_vec(),
+                location: location.clone(),
+                note: Some(format!("float constant {}", value)),
+            });
+        }
+        self.ro_offset += 8;
+    }
+
+    /// Handles the directives that put data in the read-only section, such as:
     /// hello: .asciiz 'Hello!'
-    fn handle_asciiz(&mut self, inst: &AssemblerInstruction) {
+    /// table: .byte #1 #2 #3
+    fn handle_data(&mut self, inst: &AssemblerInstruction, directive: &str) {
         // Being a constant declaration, this is only meaningful in the first pass
         if self.phase != AssemblerPhase::First { return; }
 
-        // In this case, operand1 will have the entire string we need to read in to RO memory
-        match inst.get_string_constant() {
-            Some(s) => {
-                match inst.get_label_name() {
-                    Some(name) => { self.symbols.set_symbol_offset(&name, self.ro_offset); }
-                    None => {
-                        // This would be someone typing:
-                        // .asciiz 'Hello'
-                        println!("Found a string constant with no associated label!");
-                        return;
-                    }
+        match self.encode_data(inst, directive) {
+            Ok(bytes) => {
+                // `.align` only pads, so its label names the aligned offset after the padding
+                let label_offset = if directive == "align" {
+                    self.ro_offset + bytes.len() as u32
+                } else {
+                    self.ro_offset
                 };
-                // We'll read the string into the read-only section byte-by-byte
-                for byte in s.as_bytes() {
-                    self.ro.push(*byte);
-                    self.ro_offset += 1;
+                if let Some(name) = inst.get_label_name() {
+                    self.symbols.set_symbol_offset(&name, label_offset);
+                    // `len(@label)` of a string does not count its terminator
+                    let size = match directive {
+                        "asciiz" => bytes.len() - 1,
+                        "align" => 0,
+                        _ => bytes.len(),
+                    };
+                    self.symbols.set_symbol_size(&name, size as u32);
+                }
+                if let Some(ref mut lines) = self.listing {
+                    lines.push(ListingLine {
+                        section: Section::ReadOnly,
+                        address: self.ro_offset,
+                        bytes: bytes.clone(),
+                        location: inst.locations.directive.clone(),
+                        note: None,
+                    });
+                }
+                self.ro_offset += bytes.len() as u32;
+                self.ro.extend(bytes);
+            }
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    /// The bytes a data directive stores at the current end of the read-only section
+    fn encode_data(&mut self, inst: &AssemblerInstruction, directive: &str) -> Result<Vec<u8>, AssemblerError> {
+        let operands = inst.operands();
+        let wrong_operand = |expected, location: Option<&SourceLocation>| AssemblerError::WrongDirectiveOperand {
+            directive: directive.to_string(),
+            expected,
+            location: location.unwrap_or(&inst.locations.directive).clone(),
+        };
+
+        let mut bytes = vec![];
+        match directive {
+            "ascii" | "asciiz" => {
+                match operands.as_slice() {
+                    [(Token::IrString { name }, _)] => bytes.extend_from_slice(name.as_bytes()),
+                    _ => return Err(wrong_operand("a single string", operands.get(1).map(|o| o.1))),
                 }
                 // This is the null termination bit we are using to indicate a string has ended
-                self.ro.push(0);
-                self.ro_offset += 1;
+                if directive == "asciiz" {
+                    bytes.push(0);
+                }
             }
-            None => {
-                // This just means someone typed `.asciiz` for some reason
-                println!("String constant following an .asciiz was empty");
+            "byte" | "half" | "word" => {
+                let width = match directive {
+                    "byte" => 1,
+                    "half" => 2,
+                    _ => 4,
+                };
+                let expected = if width == 1 { "8 bits integers" } else { "integers or labels" };
+                if operands.is_empty() {
+                    return Err(wrong_operand(expected, None));
+                }
+                for (token, location) in operands {
+                    let value = match token {
+                        Token::IntegerOperand { value } => {
+                            bytes.extend(encode_integer(*value, width, location)?);
+                            continue;
+                        }
+                        Token::LabelUsage { name } if width > 1 => Expr::Label(name.clone()),
+                        Token::Expression { expr } => expr.clone(),
+                        _ => return Err(wrong_operand(expected, Some(location))),
+                    };
+                    // The first pass already folded what it could, what is left uses code labels,
+                    // which only get their address at the end of the first pass
+                    self.data_fixups.push(DataFixup {
+                        offset: self.ro.len() + bytes.len(),
+                        width,
+                        value,
+                        location: location.clone(),
+                    });
+                    bytes.extend(vec![0; width]);
+                }
+            }
+            "float" => {
+                if operands.is_empty() {
+                    return Err(wrong_operand("numbers", None));
+                }
+                for (token, location) in operands {
+                    let value = match token {
+                        Token::FloatOperand { value } if !value.is_finite() => {
+                            return Err(AssemblerError::FloatOperandOutOfRange { location: location.clone() })
+                        }
+                        Token::FloatOperand { value } => *value,
+                        Token::IntegerOperand { value } => *value as f64,
+                        Token::Expression { expr } => expr.evaluate(&self.symbols, location)? as f64,
+                        _ => return Err(wrong_operand("numbers", Some(location))),
+                    };
+                    bytes.write_f64::<LittleEndian>(value).unwrap();
+                }
+            }
+            "space" | "align" => {
+                let (value, location) = match operands.as_slice() {
+                    [(Token::IntegerOperand { value }, location)] => (*value, *location),
+                    [(Token::Expression { expr }, location)] => (expr.evaluate(&self.symbols, location)?, *location),
+                    _ => return Err(wrong_operand("a single integer", operands.get(1).map(|o| o.1))),
+                };
+                if value < 0 || value > i64::from(u16::MAX) {
+                    return Err(AssemblerError::IntegerOperandOutOfRange { value, bits: 16, location: location.clone() });
+                }
+                let count = if directive == "space" {
+                    value as u32
+                } else if value != 0 && value & (value - 1) == 0 {
+                    (value as u32 - self.ro_offset % value as u32) % value as u32
+                } else {
+                    return Err(AssemblerError::InvalidAlignment { value, location: location.clone() });
+                };
+                bytes.extend(vec![0; count as usize]);
+            }
+            _ => unreachable!("{} is not a data directive", directive),
+        }
+        Ok(bytes)
+    }
+
+    /// Writes the data values that use labels once all labels have their final value
+    fn apply_data_fixups(&mut self) {
+        for fixup in mem::take(&mut self.data_fixups) {
+            if self.relocatable && fixup.value.uses_label() {
+                match self.relocation(&fixup.value, &fixup.location) {
+                    Ok((target, addend)) => self.relocations.push(Relocation {
+                        section: Section::ReadOnly,
+                        offset: fixup.offset as u32,
+                        field: Field::Data { width: fixup.width as u8 },
+                        target,
+                        addend,
+                    }),
+                    Err(e) => self.errors.push(e),
+                }
+            }
+            let bytes = fixup
+                .value
+                .evaluate(&self.symbols, &fixup.location)
+                .and_then(|value| encode_integer(value, fixup.width, &fixup.location));
+            match bytes {
+                Ok(bytes) => self.ro[fixup.offset..fixup.offset + fixup.width].copy_from_slice(&bytes),
+                Err(e) => self.errors.push(e),
+            }
+        }
+    }
+
+    /// Handles the declaration of a constant:
+    /// .equ BUF_SIZE #64
+    fn handle_constant(&mut self, inst: &AssemblerInstruction, directive: &str) {
+        // Constants get their value in the first pass, before anything that uses them is encoded
+        if self.phase != AssemblerPhase::First { return; }
+
+        let operands = inst.operands();
+        let (name, name_location, value) = match operands.as_slice() {
+            [(Token::Name { name }, name_location), (value, location)] => {
+                let value = match value {
+                    Token::IntegerOperand { value } => Ok(*value),
+                    Token::LabelUsage { name } => Expr::Label(name.clone()).evaluate(&self.symbols, location),
+                    Token::Expression { expr } => expr.evaluate(&self.symbols, location),
+                    _ => Err(AssemblerError::WrongDirectiveOperand {
+                        directive: directive.to_string(),
+                        expected: "a name and an integer",
+                        location: (*location).clone(),
+                    }),
+                };
+                (name, name_location, value)
+            }
+            _ => {
+                self.errors.push(AssemblerError::WrongDirectiveOperand {
+                    directive: directive.to_string(),
+                    expected: "a name and an integer",
+                    location: operands.get(2).map_or(&inst.locations.directive, |o| o.1).clone(),
+                });
+                return;
             }
+        };
+
+        if self.symbols.has_symbol(name) {
+            self.errors.push(AssemblerError::SymbolAlreadyDeclared { name: name.clone(), location: (*name_location).clone() });
+            return;
+        }
+        match value {
+            Ok(value) => {
+                self.symbols.add_symbol(Symbol::new_constant(name.clone(), value).with_location((*name_location).clone()));
+            }
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    /// Handles the directives that link objects together:
+    /// .global main
+    /// .extern print_line
+    fn handle_linkage(&mut self, inst: &AssemblerInstruction, directive: &str) {
+        if self.phase != AssemblerPhase::First { return; }
+
+        let operands = inst.operands();
+        let (name, location) = match operands.as_slice() {
+            [(Token::Name { name }, location)] => (name.clone(), (*location).clone()),
+            _ => {
+                self.errors.push(AssemblerError::WrongDirectiveOperand {
+                    directive: directive.to_string(),
+                    expected: "a single symbol name",
+                    location: operands.get(1).map_or(&inst.locations.directive, |o| o.1).clone(),
+                });
+                return;
+            }
+        };
+
+        if directive == "global" {
+            // Checked once all labels are declared
+            self.exports.push((name, location));
+            return;
+        }
+        if !self.relocatable {
+            self.errors.push(AssemblerError::UnlinkedExternal { name, location });
+            return;
+        }
+        if self.symbols.has_symbol(&name) {
+            self.errors.push(AssemblerError::SymbolAlreadyDeclared { name, location });
+            return;
         }
+        // Encoding needs a value, the linker writes the real address over it
+        self.symbols.add_symbol(Symbol::new_with_offset(name, SymbolType::External, 0).with_location(location));
     }
+
+    /// Every `.global` has to name a label of this file
+    fn check_exports(&mut self) {
+        for (name, location) in &self.exports {
+            if self.symbols.symbol_section(name).is_some() {
+                self.symbols.set_symbol_visibility(name, Visibility::Global);
+                continue;
+            }
+            let error = if self.symbols.has_symbol(name) {
+                AssemblerError::WrongDirectiveOperand {
+                    directive: "global".to_string(),
+                    expected: "a label declared in this file",
+                    location: location.clone(),
+                }
+            } else {
+                AssemblerError::UnknownLabel { name: name.clone(), location: location.clone() }
+            };
+            self.errors.push(error);
+        }
+    }
+
+    /// Records the operands of an instruction at `offset` in the code that use labels,
+    /// along with the read-only offset of a pooled float
+    fn relocate_instruction(&mut self, inst: &AssemblerInstruction, offset: u32) {
+        if let Some((_, value)) = inst.float_constant() {
+            if let Some(ro_offset) = self.symbols.float_constant_offset(value) {
+                self.relocations.push(Relocation {
+                    section: Section::Code,
+                    offset: offset + 2,
+                    field: Field::Operand16,
+                    target: RelocationTarget::Section(Section::ReadOnly),
+                    addend: i64::from(ro_offset),
+                });
+            }
+            return;
+        }
+
+        for (field, expr, location) in inst.label_operands() {
+            if field + 2 > INSTRUCTION_LENGTH as usize {
+                self.errors.push(AssemblerError::InvalidExpression {
+                    error: "only 16 bits operands can hold a linked address",
+                    location: location.clone(),
+                });
+                continue;
+            }
+            match self.relocation(&expr, location) {
+                Ok((target, addend)) => self.relocations.push(Relocation {
+                    section: Section::Code,
+                    offset: offset + field as u32,
+                    field: Field::Operand16,
+                    target,
+                    addend,
+                }),
+                Err(e) => self.errors.push(e),
+            }
+        }
+    }
+
+    /// What the linker computes the value of `expr` from.
+    /// Labels of this file are relative to their section, external ones are left to the linker
+    fn relocation(&self, expr: &Expr, location: &SourceLocation) -> Result<(RelocationTarget, i64), AssemblerError> {
+        let (name, addend) = expr.split_label().ok_or_else(|| AssemblerError::InvalidExpression {
+            error: "only a label plus or minus a constant can be linked",
+            location: location.clone(),
+        })?;
+        let addend = addend.evaluate(&self.symbols, location)?;
+        let value = match self.symbols.symbol_value(&name) {
+            Some(value) => i64::from(value),
+            None => return Err(AssemblerError::UnknownLabel { name, location: location.clone() }),
+        };
+        match self.symbols.symbol_section(&name) {
+            Some(Section::ReadOnly) => Ok((RelocationTarget::Section(Section::ReadOnly), value + addend)),
+            Some(Section::Code) => {
+                Ok((RelocationTarget::Section(Section::Code), value - i64::from(self.code_start()) + addend))
+            }
+            None => Ok((RelocationTarget::Symbol(name), addend)),
+        }
+    }
+}
+
+/// `value` as `width` little endian bytes, if it fits as either a signed or an unsigned integer
+fn encode_integer(value: i64, width: usize, location: &SourceLocation) -> Result<Vec<u8>, AssemblerError> {
+    let bits = 8 * width as u8;
+    if value < -(1 << (bits - 1)) || value >= 1 << bits {
+        return Err(AssemblerError::IntegerOperandOutOfRange { value, bits, location: location.clone() });
+    }
+    let mut bytes = vec![];
+    bytes.write_uint::<LittleEndian>(value as u64 & ((1 << bits) - 1), width).unwrap();
+    Ok(bytes)
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// A `.byte`, `.half` or `.word` value that uses labels, written once labels have their final value
+#[derive(Debug)]
+struct DataFixup {
+    /// Where the value goes in the read-only section
+    offset: usize,
+    /// Size of the value in bytes
+    width: usize,
+    value: Expr,
+    location: SourceLocation,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
 pub enum AssemblerSection {
     Data { starting_instruction: Option<u32> },
     Code { starting_instruction: Option<u32> },
+    #[default]
     Unknown,
 }
 
-impl Default for AssemblerSection {
-    fn default() -> Self {
-        AssemblerSection::Unknown
-    }
-}
-
-impl<'a> From<&'a str> for AssemblerSection {
+impl From<&str> for AssemblerSection {
     fn from(name: &str) -> AssemblerSection {
         match name {
             "data" => {
@@ -305,9 +885,11 @@ impl<'a> From<&'a str> for AssemblerSection {
 
 #[cfg(test)]
 mod tests {
-    #![allow(unused_imports)]
+    #![allow(unused_imports, clippy::bool_assert_comparison)]
 
     use super::*;
+    use std::env;
+    use instruction::OperandKind;
     use vm::VM;
 
     #[test]
@@ -358,9 +940,9 @@ mod tests {
     /// Tests that we can add things to the symbol table
     fn test_symbol_table() {
         let mut sym = SymbolTable::new();
-        let new_symbol = Symbol::new_with_offset("test".to_string(), SymbolType::Label, 12);
+        let new_symbol = Symbol::new_with_offset("test".to_string(), SymbolType::CodeLabel, 12);
         sym.add_symbol(new_symbol);
-        assert_eq!(sym.symbols.len(), 1);
+        assert_eq!(sym.len(), 1);
         let v = sym.symbol_value("test");
         assert_eq!(true, v.is_some());
         let v = v.unwrap();
@@ -400,10 +982,10 @@ mod tests {
     fn test_first_phase_no_segment() {
         let mut asm = Assembler::new();
         let test_string = "hello: .asciiz 'Fail'";
-        let result = parse_program(CompleteStr(test_string));
+        let result = parse_program(test_string, DEFAULT_FILE_NAME);
         assert_eq!(result.is_ok(), true);
-        let (_, p) = result.unwrap();
-        asm.process_first_phase(&p);
+        let mut p = result.unwrap();
+        asm.process_first_phase(&mut p);
         assert_eq!(asm.errors.len(), 1);
     }
 
@@ -415,10 +997,747 @@ mod tests {
         .data
         test: .asciiz 'Hello'
         ";
-        let result = parse_program(CompleteStr(test_string));
+        let result = parse_program(test_string, DEFAULT_FILE_NAME);
         assert_eq!(result.is_ok(), true);
-        let (_, p) = result.unwrap();
-        asm.process_first_phase(&p);
+        let mut p = result.unwrap();
+        asm.process_first_phase(&mut p);
         assert_eq!(asm.errors.len(), 0);
     }
+
+    #[test]
+    /// Tests that code labels resolve to absolute addresses after the header and read-only section
+    fn test_code_label_offsets() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        hello: .asciiz 'Hi'
+        .code
+        load $0 #1
+        start: inc $0
+        hlt
+        ";
+        asm.assemble(test_string).unwrap();
+        assert_eq!(asm.symbols.symbol_value("hello"), Some(0));
+        assert_eq!(asm.symbols.symbol_value("start"), Some(PIE_HEADER_LENGTH as u32 + 3 + 4));
+    }
+
+    #[test]
+    /// Tests that jumping to a label runs a loop the expected number of times
+    fn test_jump_to_label() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        load $0 #3
+        load $1 #0
+        load $2 #0
+        loop: dec $0
+        inc $2
+        neq $0 $1
+        jmpe @loop
+        jmp @end
+        load $2 #100
+        end: hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(vm.registers[2], 3);
+    }
+
+    #[test]
+    /// Tests that using a label nobody declared is an error instead of missing bytes
+    fn test_unknown_label() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        jmp @nowhere
+        ";
+        match asm.assemble(test_string) {
+            Err(errors) => match errors[0] {
+                AssemblerError::UnknownLabel { ref name, ref location } => {
+                    assert_eq!(name, "nowhere");
+                    assert_eq!(location.line, 4);
+                    assert_eq!(location.column, 13);
+                    assert_eq!(location.source_line.trim(), "jmp @nowhere");
+                }
+                ref e => panic!("Unexpected error {:?}", e),
+            },
+            Ok(_) => panic!("Expected an error"),
+        }
+    }
+
+    #[test]
+    /// Tests that errors are rendered with the source line and a caret under the problem
+    fn test_render_error() {
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.code\nload $0 #1\nloadw $0 $1 #300\n").unwrap_err();
+        assert_eq!(
+            errors[0].render(),
+            "error: Integer operand 300 does not fit in 8 bits\n\
+             \x20--> <input>:4:13\n\
+             \x20 |\n\
+             4 | loadw $0 $1 #300\n\
+             \x20 |             ^^^^\n"
+        );
+
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\nhello: .asciiz 'Hi'\n.code\nhello: hlt\n").unwrap_err();
+        let location = errors[0].location();
+        assert_eq!((location.line, location.column, location.end - location.start), (4, 1, 6));
+    }
+
+    #[test]
+    /// Tests that operands are checked against the opcode's signature
+    fn test_operand_validation() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        add $0
+        hlt $1 $2
+        load #1 #2
+        inc $200
+        jmp @nowhere $1
+        ftoi $0 $1
+        loadf64 $0 #2.5
+        ";
+        let errors = asm.assemble(test_string).unwrap_err();
+        assert_eq!(errors.len(), 5);
+        match errors[0] {
+            AssemblerError::WrongOperandCount { opcode, min, max, found, ref location } => {
+                assert_eq!((opcode, min, max, found), (Opcode::ADD, 3, 3, 1));
+                assert_eq!((location.line, location.column), (4, 9));
+            }
+            ref e => panic!("Unexpected error {:?}", e),
+        }
+        match errors[1] {
+            AssemblerError::WrongOperandCount { found, ref location, .. } => {
+                assert_eq!(found, 2);
+                assert_eq!((location.line, location.column), (5, 13));
+            }
+            ref e => panic!("Unexpected error {:?}", e),
+        }
+        match errors[2] {
+            AssemblerError::WrongOperandType { position, expected, ref location, .. } => {
+                assert_eq!(position, 1);
+                assert_eq!(expected, OperandKind::IntRegister);
+                assert_eq!((location.line, location.column), (6, 14));
+            }
+            ref e => panic!("Unexpected error {:?}", e),
+        }
+        match errors[3] {
+            AssemblerError::RegisterOutOfRange { register, .. } => assert_eq!(register, 200),
+            ref e => panic!("Unexpected error {:?}", e),
+        }
+        match errors[4] {
+            AssemblerError::WrongOperandCount { opcode, .. } => assert_eq!(opcode, Opcode::JMP),
+            ref e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        bytes: .byte #1 #-1 #255 #4 #5
+        aligned: .align #4
+        halves: .half #0x1234 @main
+        words: .word #-2 @main
+        room: .space #3
+        text: .ascii 'ab'
+        ratio: .float #2.5
+        .code
+        main: loadcf64 $0 @ratio
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.symbols.symbol_value("aligned"), Some(8));
+        assert_eq!(asm.symbols.symbol_value("room"), Some(20));
+        assert_eq!(asm.symbols.symbol_value("main"), Some(97));
+        assert_eq!(&asm.ro[..25], &[
+            1, 255, 255, 4, 5, 0, 0, 0,
+            0x34, 0x12, 97, 0,
+            254, 255, 255, 255, 97, 0, 0, 0,
+            0, 0, 0,
+            97, 98,
+        ]);
+        assert_eq!(&asm.ro[25..], &2.5f64.to_bits().to_le_bytes());
+
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(vm.float_registers[0], 2.5);
+    }
+
+    #[test]
+    fn test_data_directive_errors() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\n.byte #256\n.half 'x'\n.align #3\n.space\n.word @nowhere\n.code\nhlt\n";
+        let errors = asm.assemble(test_string).unwrap_err();
+        assert_eq!(errors.len(), 5);
+        match errors[0] {
+            AssemblerError::IntegerOperandOutOfRange { value, bits, .. } => assert_eq!((value, bits), (256, 8)),
+            ref e => panic!("Unexpected error {:?}", e),
+        }
+        assert_eq!(errors[1].to_string(), "`.half` expects integers or labels");
+        assert_eq!(errors[1].location().column, 7);
+        assert_eq!(errors[2].to_string(), "Alignment 3 is not a power of two");
+        assert_eq!(errors[3].to_string(), "`.space` expects a single integer");
+        match errors[4] {
+            AssemblerError::UnknownLabel { ref name, ref location } => {
+                assert_eq!(name, "nowhere");
+                assert_eq!(location.line, 6);
+            }
+            ref e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_unknown_section_header() {
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.cdoe\nhlt\n.code\n.bogus #1\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "Invalid or unknown directive found. Directive name was: cdoe");
+        assert_eq!((errors[0].location().line, errors[0].location().column), (2, 1));
+        assert_eq!(errors[1].to_string(), "Invalid or unknown directive found. Directive name was: bogus");
+    }
+
+    #[test]
+    fn test_constants_and_expressions() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .equ SIZE #4
+        msg: .asciiz 'Hello'
+        .const MSG_LEN #len(@msg)
+        table: .word #SIZE*2+1 @main @main+4 #-(SIZE)
+        .code
+        main: load $0 #SIZE*0x10000
+        load $1 #MSG_LEN%4
+        load $2 @table+4
+        jmp @end+0
+        hlt
+        end: hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.symbols.constant_value("MSG_LEN"), Some(5));
+        assert_eq!(&asm.ro[6..], &[9, 0, 0, 0, 86, 0, 0, 0, 90, 0, 0, 0, 252, 255, 255, 255]);
+
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(&vm.registers[..3], &[0x40000, 1, 10]);
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .macro addi \dst \value
+        load $31 \value
+        add \dst $31 \dst
+        .endm
+        .macro skip_inc \reg
+        jmp @over
+        inc \reg
+        over: inc \reg
+        .endm
+        .data
+        .code
+        start: addi $0 #40
+        addi $0 #2
+        skip_inc $1
+        skip_inc $1
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.symbols.symbol_value("start"), Some(64));
+        assert_eq!(asm.symbols.has_symbol("over~3"), true);
+
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(&vm.registers[..2], &[42, 2]);
+    }
+
+    #[test]
+    fn test_symbol_kinds() {
+        let mut asm = Assembler::new();
+        asm.set_file_name("prog.iasm");
+        asm.assemble_object(".extern print\n.data\nmsg: .asciiz 'Hi'\n.equ SIZE #4\n.code\n.global main\nmain: call @print\nhlt\n")
+            .unwrap();
+        let kinds: Vec<(&str, SymbolType, Visibility)> =
+            asm.symbols.iter().map(|symbol| (symbol.name(), *symbol.symbol_type(), symbol.visibility())).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("print", SymbolType::External, Visibility::Global),
+                ("msg", SymbolType::DataLabel, Visibility::Local),
+                ("SIZE", SymbolType::Constant, Visibility::Local),
+                ("main", SymbolType::CodeLabel, Visibility::Global),
+            ]
+        );
+        let msg = asm.symbols.get("msg").unwrap();
+        assert_eq!((msg.offset(), msg.size()), (Some(0), Some(2)));
+        assert_eq!(msg.location().unwrap().to_string(), "prog.iasm:3:1");
+        assert_eq!(asm.symbols.get("main").unwrap().offset(), Some(PIE_HEADER_LENGTH as u32 + 3));
+    }
+
+    #[test]
+    fn test_pseudo_instructions() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        main: li $0 #70000
+        clr $1
+        li $2 #5
+        loop: inc $1
+        blt $1 $2 @loop
+        bne $1 $2 @fail
+        beq $1 $2 @done
+        fail: li $3 #1
+        done: mov $1 $4
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        // The wide `li` takes two instructions and each branch takes two
+        assert_eq!(asm.symbols.symbol_value("loop"), Some(PIE_HEADER_LENGTH as u32 + 16));
+        assert_eq!(asm.symbols.symbol_value("done"), Some(PIE_HEADER_LENGTH as u32 + 48));
+
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(&vm.registers[..5], &[70000, 5, 5, 0, 5]);
+
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.code\nbge $1 #2 @end\nend: hlt\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "`bge` expects two registers and a branch target");
+    }
+
+    #[test]
+    fn test_register_aliases() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        .reg total $s1
+        main: li $a0 #20
+        li $a1 #22
+        call @sum
+        mov $v0 $total
+        loadf64 $f2 #1.5
+        hlt
+        sum: add $a0 $a1 $v0
+        ret
+        ";
+        let program = asm.assemble(test_string).unwrap();
+
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!((vm.registers[1], vm.registers[20]), (42, 42));
+        assert_eq!(vm.float_registers[2], 1.5);
+    }
+
+    #[test]
+    fn test_register_files() {
+        let mut asm = Assembler::new();
+        assert_eq!(asm.assemble(".data\n.code\nloadf64 $3 #1.5\nadd $3 $3 $3\nhlt\n").is_ok(), true);
+
+        let mut asm = Assembler::new();
+        let errors = asm
+            .assemble(".data\n.code\n.reg x $f3\nadd $f1 $1 $2\nloadf64 $t0 #1.5\ninc $x\naddf64 $f1 $f2 $f3\n")
+            .unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].to_string(), "Operand 1 of `add` should be an integer register");
+        assert_eq!((errors[0].location().line, errors[0].location().column), (4, 5));
+        assert_eq!(errors[1].to_string(), "Operand 1 of `loadf64` should be a float register");
+        assert_eq!(errors[2].to_string(), "Operand 1 of `inc` should be an integer register");
+    }
+
+    #[test]
+    fn test_local_labels() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .macro skip_one \reg
+        jmp @1f
+        inc $0
+        1: inc \reg
+        .endm
+        .data
+        .equ buf.size #2
+        .code
+        main_entry: jmp @.skip
+        inc $0
+        .skip: inc $1
+        jmp @1f
+        inc $0
+        1: inc $1
+        jmp @1f
+        1: jmp @.done
+        inc $0
+        .done: skip_one $2
+        skip_one $2
+        load $3 #buf.size
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.symbols.has_symbol("main_entry.skip"), true);
+        assert_eq!(asm.symbols.has_symbol("main_entry.done"), true);
+
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(&vm.registers[..4], &[0, 2, 2, 2]);
+
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.code\nmain: hlt\n.end: hlt\nmain.end: hlt\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "Symbol main.end was previously declared.");
+    }
+
+    #[test]
+    fn test_macro_error_rendering() {
+        let mut asm = Assembler::new();
+        let errors = asm
+            .assemble(".macro big \\r\nloadw \\r \\r #300\n.endm\n.data\n.code\nbig $1\nhlt\n")
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].render(),
+            "error: Integer operand 300 does not fit in 8 bits\n \
+             --> <input>:2:13\n  |\n2 | loadw \\r \\r #300\n  |             ^^^^\n \
+             ::: <input>:6:1\n  |\n6 | big $1\n  | ------ in this expansion of `big`\n"
+        );
+    }
+
+    #[test]
+    fn test_assemble_file() {
+        let dir = env::temp_dir().join("iridium-assemble-file");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.iasm"), ".include \"lib/consts.iasm\"\n.data\n.code\naddi $0 #ANSWER\nhlt\n").unwrap();
+        fs::write(dir.join("lib/consts.iasm"), ".equ ANSWER #42\n.include \"macros.iasm\"\n").unwrap();
+        fs::write(dir.join("lib/macros.iasm"), ".macro addi \\dst \\value\nload $31 \\value\nadd \\dst $31 \\dst\n.endm\n").unwrap();
+
+        let program = Assembler::new().assemble_file(dir.join("main.iasm")).unwrap();
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(vm.registers[0], 42);
+
+        fs::write(dir.join("lib/macros.iasm"), "hlt $1\n").unwrap();
+        let errors = Assembler::new().assemble_file(dir.join("main.iasm")).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].location().to_string(), format!("{}:1:5", dir.join("lib").join("macros.iasm").display()));
+
+        let errors = Assembler::new().assemble_file(dir.join("missing.iasm")).unwrap_err();
+        assert_eq!(errors[0].to_string(), format!("Cannot read \"{}\": entity not found", dir.join("missing.iasm").display()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_with_sections() {
+        let dir = env::temp_dir().join("iridium-include-sections");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.iasm"), ".data\nfirst: .byte #1\n.code\njmp @main\n.include \"lib.iasm\"\n.code\nmain: load $0 @second\nhlt\n")
+            .unwrap();
+        fs::write(dir.join("lib.iasm"), ".data\nsecond: .byte #2\n.code\ndouble: add $0 $0 $0\n").unwrap();
+
+        let mut asm = Assembler::new();
+        asm.assemble_file(dir.join("main.iasm")).unwrap();
+        assert_eq!(asm.ro, vec![1, 2]);
+        assert_eq!(asm.symbols.symbol_value("second"), Some(1));
+        assert_eq!(asm.symbols.symbol_value("double"), Some(PIE_HEADER_LENGTH as u32 + 2 + 4));
+        assert_eq!(asm.symbols.symbol_value("main"), Some(PIE_HEADER_LENGTH as u32 + 2 + 8));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_assemble_object() {
+        let mut asm = Assembler::new();
+        let object = asm
+            .assemble_object(".extern print\n.data\nmsg: .asciiz 'Hi'\n.code\n.global main\nmain: load $0 @msg\ncall @print\njmp @main+4\n")
+            .unwrap();
+        assert_eq!(object.exports, vec![ExportedSymbol { name: "main".to_string(), section: Section::Code, offset: 0 }]);
+        assert_eq!(object.imports, vec!["print".to_string()]);
+        let targets: Vec<(u32, RelocationTarget, i64)> =
+            object.relocations.iter().map(|r| (r.offset, r.target.clone(), r.addend)).collect();
+        assert_eq!(
+            targets,
+            vec![
+                (2, RelocationTarget::Section(Section::ReadOnly), 0),
+                (5, RelocationTarget::Symbol("print".to_string()), 0),
+                (9, RelocationTarget::Section(Section::Code), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_linkage_errors() {
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".extern print\n.data\n.equ SIZE #1\n.global SIZE\n.global nowhere\n.code\nhlt\n").unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].to_string(), "print is declared .extern, so the file has to be assembled as an object and linked");
+        assert_eq!(errors[1].to_string(), "`.global` expects a label declared in this file");
+        assert_eq!(errors[2].to_string(), "Label @nowhere was used but never declared");
+
+        let mut asm = Assembler::new();
+        let errors = asm.assemble_object(".data\n.code\nloop: load $0 @loop*2\nhlt\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "Invalid expression: only a label plus or minus a constant can be linked");
+    }
+
+    #[test]
+    fn test_forward_constants() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $0 #SMALL\nload $1 #BIG\nafter: hlt\n.equ SMALL #300\n.equ BIG #70000\n")
+            .unwrap();
+        // The wide load of BIG takes two instructions even though BIG is declared after it
+        assert_eq!(asm.symbols.symbol_value("after"), Some(PIE_HEADER_LENGTH as u32 + 12));
+
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(&vm.registers[..2], &[300, 70000]);
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.equ SIZE #1\n.equ SIZE #2\n.equ OTHER #MISSING\n.code\nhlt\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "Symbol SIZE was previously declared.");
+        assert_eq!((errors[0].location().line, errors[0].location().column), (3, 6));
+        assert_eq!(errors[1].to_string(), "Constant MISSING was used but never declared with .equ");
+
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.code\nload $0 #LATER/0\nload $1 #UNKNOWN\n.equ LATER #1\nhlt\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "Invalid expression: division by zero");
+        assert_eq!((errors[0].location().line, errors[0].location().column), (3, 9));
+        match errors[1] {
+            AssemblerError::UnknownConstant { ref name, .. } => assert_eq!(name, "UNKNOWN"),
+            ref e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.code\nlood $0 #1\nfrob $0\nigl\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        match errors[0] {
+            AssemblerError::UnknownOpcode { ref name, suggestion, ref location } => {
+                assert_eq!(name, "lood");
+                assert_eq!(suggestion, Some("load"));
+                assert_eq!((location.line, location.column), (3, 1));
+            }
+            ref e => panic!("Unexpected error {:?}", e),
+        }
+        assert_eq!(errors[0].to_string(), "Unknown opcode `lood`, did you mean `load`?");
+        assert_eq!(errors[1].to_string(), "Unknown opcode `frob`");
+    }
+
+    #[test]
+    /// Tests calling a subroutine declared after the caller
+    fn test_call_subroutine() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        load $0 #1
+        call @double
+        call @double
+        hlt
+        double: add $0 $0 $0
+        ret
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(vm.registers[0], 4);
+        assert_eq!(vm.stack().len(), 0);
+    }
+
+    #[test]
+    /// Tests building an array on the heap
+    fn test_heap_array() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        load $0 #8
+        aloc $0
+        load $1 #0
+        load $2 #513
+        storew $2 $1 #4
+        loadh $3 $1 #4
+        loadb $4 $1 #5
+        free $0
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(vm.registers[3], 513);
+        assert_eq!(vm.registers[4], 2);
+    }
+
+    #[test]
+    /// Tests that a heap offset has to fit in one byte
+    fn test_heap_offset_out_of_range() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        loadw $0 $1 #256
+        ";
+        match asm.assemble(test_string) {
+            Err(errors) => match errors[0] {
+                AssemblerError::IntegerOperandOutOfRange { value, bits, .. } => {
+                    assert_eq!(value, 256);
+                    assert_eq!(bits, 8);
+                }
+                ref e => panic!("Unexpected error {:?}", e),
+            },
+            Ok(_) => panic!("Expected an error"),
+        }
+    }
+
+    #[test]
+    /// Tests that immediates wider than 16 bits are loaded with a LOAD/LOADHI pair
+    fn test_wide_immediates() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        load $0 #-1
+        load $1 #70000
+        load $2 #0xFFFF
+        load $3 #'A'
+        after: hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(program.len(), PIE_HEADER_LENGTH + 7 * 4);
+        assert_eq!(asm.symbols.symbol_value("after"), Some(PIE_HEADER_LENGTH as u32 + 24));
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(vm.registers[0], -1);
+        assert_eq!(vm.registers[1], 70000);
+        assert_eq!(vm.registers[2], 0xFFFF);
+        assert_eq!(vm.registers[3], 65);
+    }
+
+    #[test]
+    /// Tests that immediates are checked against the width of their field
+    fn test_immediate_out_of_range() {
+        let cases = [
+            ("load $0 #0x100000000", 0x1_0000_0000, 32),
+            ("djmpe #70000", 70000, 16),
+            ("prts #-1", -1, 16),
+        ];
+        for &(line, expected_value, expected_bits) in cases.iter() {
+            let mut asm = Assembler::new();
+            let test_string = format!(".data\n.code\n{}\n", line);
+            match asm.assemble(&test_string) {
+                Err(errors) => match errors[0] {
+                    AssemblerError::IntegerOperandOutOfRange { value, bits, .. } => {
+                        assert_eq!(value, expected_value);
+                        assert_eq!(bits, expected_bits);
+                    }
+                    ref e => panic!("Unexpected error {:?}", e),
+                },
+                Ok(_) => panic!("Expected an error for {}", line),
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that float immediates are loaded with full precision from the read-only section
+    fn test_float_immediates() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        hello: .asciiz 'Hi'
+        .code
+        loadf64 $0 #2.75
+        loadf64 $1 #-2.5e-3
+        loadf64 $2 #2.75
+        loadf64 $3 #-2
+        loadf64 $4 #500
+        after: hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        // 'Hi' plus three distinct constants
+        assert_eq!(asm.ro.len(), 3 + 3 * 8);
+        assert_eq!(asm.symbols.symbol_value("after"), Some((PIE_HEADER_LENGTH + 27 + 20) as u32));
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(vm.float_registers[0], 2.75);
+        assert_eq!(vm.float_registers[1], -2.5e-3);
+        assert_eq!(vm.float_registers[2], 2.75);
+        assert_eq!(vm.float_registers[3], -2.0);
+        assert_eq!(vm.float_registers[4], 500.0);
+    }
+
+    #[test]
+    fn test_float_overflow() {
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.code\nloadf64 $f0 #1e400\nhlt\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "Float operand is too large for a 64 bits float");
+        assert_eq!((errors[0].location().line, errors[0].location().column), (3, 13));
+
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\nbig: .float #1.5 #-1e309\n.code\nhlt\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].location().line, errors[0].location().column), (2, 18));
+    }
+
+    #[test]
+    /// Tests moving values between the integer and float register files
+    fn test_register_file_conversions() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        load $0 #9
+        itof $0 $0
+        sqrtf64 $0 $1
+        loadf64 $2 #-1.5
+        ftoi $2 $1 #1
+        mov $1 $2
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(vm.float_registers[1], 3.0);
+        assert_eq!(vm.registers[1], -2);
+        assert_eq!(vm.registers[2], -2);
+    }
+
+    #[test]
+    fn test_conversion_mode_out_of_range() {
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.code\nftoi $f0 $1 #3\nftoi $f0 $1 #7\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "Float to integer conversion mode 7 does not exist, modes go from 0 to 3");
+        assert_eq!((errors[0].location().line, errors[0].location().column), (4, 13));
+    }
 }
@@ -1,14 +1,17 @@
 use assembler::Token;
 use instruction::Opcode;
-use nom::alpha1;
 use nom::types::CompleteStr;
 
 /// Parser for opcode
 named!(pub parse_opcode<CompleteStr, Token>,
     do_parse!(
-        opcode: alpha1 >>
+        // Mnemonics such as `loadf64` end with digits, and macro names may have underscores
+        opcode: take_while1!(|c: char| c.is_ascii_alphanumeric() || c == '_') >>
         (
-            Token::Op {code: Opcode::from(opcode)}
+            match Opcode::from_mnemonic(opcode.0) {
+                Some(code) => Token::Op { code },
+                None => Token::UnknownOp { name: opcode.0.to_string() },
+            }
         )
     )
 );
@@ -16,7 +19,7 @@ named!(pub parse_opcode<CompleteStr, Token>,
 
 #[cfg(test)]
 mod tests {
-    #![allow(unused_imports)]
+    #![allow(unused_imports, clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -28,8 +31,17 @@ mod tests {
         assert_eq!(token, Token::Op{code: Opcode::LOAD});
         assert_eq!(res, CompleteStr(""));
 
+        let result = parse_opcode(CompleteStr("loadf64 $0"));
+        let (res, token) = result.unwrap();
+        assert_eq!(token, Token::Op { code: Opcode::LOADF64 });
+        assert_eq!(res, CompleteStr(" $0"));
+
         let result = parse_opcode(CompleteStr("aold"));
         let (_, token) = result.unwrap();
+        assert_eq!(token, Token::UnknownOp { name: "aold".to_string() });
+
+        let result = parse_opcode(CompleteStr("igl"));
+        let (_, token) = result.unwrap();
         assert_eq!(token, Token::Op { code: Opcode::IGL });
     }
 }
\ No newline at end of file
@@ -1,33 +1,179 @@
-use assembler::label_parsers::parse_label_usage;
+use assembler::expressions::{BinaryOp, Expr};
+use assembler::label_parsers::parse_label_name;
 use assembler::register_parsers::parse_register;
 use assembler::Token;
-use nom::digit;
+use nom::{alpha1, anychar, digit, hex_digit};
 use nom::types::CompleteStr;
 
 /// Parser for all kinds of operand
 named!(pub parse_operand<CompleteStr, Token>,
     alt!(
-        parse_integer_operand |
         parse_float_operand |
-        parse_label_usage |
+        parse_integer_operand |
+        parse_label_operand |
         parse_register |
-        parse_irstring
+        parse_irstring |
+        parse_macro_parameter
+    )
+);
+
+/// Parser for a parameter of a macro, such as `\reg`, which calls replace with an operand
+named!(pub parse_macro_parameter<CompleteStr, Token>,
+    ws_comments!(
+        do_parse!(
+            char!('\\') >>
+            name: parse_identifier >>
+            (
+                Token::MacroParameter { name: name.to_string() }
+            )
+        )
     )
 );
 
-/// Parser for integer numbers, which we preface with `#` in our assembly language
+/// Parser for integer numbers, which we preface with `#` in our assembly language.
+/// Accepts decimal, hex (`#0xFF`), binary (`#0b1010`) and character (`#'a'`) literals,
+/// and expressions of them, constants and labels such as `#SIZE*2+1`.
+/// Range checks happen in the assembler, which knows how wide the operand's field is.
 named!(pub parse_integer_operand<CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
             tag!("#") >>
-            reg_num: digit >>
+            expr: parse_expression >>
             (
-                Token::IntegerOperand{value: reg_num.parse::<i32>().unwrap()}
+                match expr.literal_value() {
+                    Some(value) => Token::IntegerOperand { value },
+                    None => Token::Expression { expr },
+                }
+            )
+        )
+    )
+);
+
+/// Parser for a label, `@name`, or an expression starting with one such as `@table+4`
+named!(pub parse_label_operand<CompleteStr, Token>,
+    ws_comments!(
+        do_parse!(
+            peek!(tag!("@")) >>
+            expr: parse_expression >>
+            (
+                match expr {
+                    Expr::Label(name) => Token::LabelUsage { name },
+                    expr => Token::Expression { expr },
+                }
             )
         )
     )
 );
 
+/// Parser for an integer expression with `+ - * / %`, parentheses and the usual precedence.
+/// Whitespace separates operands, so there can't be any inside an expression
+named!(pub parse_expression<CompleteStr, Expr>,
+    do_parse!(
+        first: parse_term >>
+        expr: fold_many0!(
+            pair!(one_of!("+-"), parse_term),
+            first,
+            |left, (op, right)| binary(if op == '+' { BinaryOp::Add } else { BinaryOp::Sub }, left, right)
+        ) >>
+        (expr)
+    )
+);
+
+named!(parse_term<CompleteStr, Expr>,
+    do_parse!(
+        first: parse_factor >>
+        expr: fold_many0!(
+            pair!(one_of!("*/%"), parse_factor),
+            first,
+            |left, (op, right)| {
+                let op = match op {
+                    '*' => BinaryOp::Mul,
+                    '/' => BinaryOp::Div,
+                    _ => BinaryOp::Rem,
+                };
+                binary(op, left, right)
+            }
+        ) >>
+        (expr)
+    )
+);
+
+named!(parse_factor<CompleteStr, Expr>,
+    alt!(
+        map!(alt!(parse_char_literal | parse_hex_literal | parse_binary_literal | parse_decimal_literal), Expr::Number) |
+        do_parse!(
+            tag!("len(@") >>
+            name: parse_label_name >>
+            char!(')') >>
+            (Expr::Len(name.to_string()))
+        ) |
+        map!(preceded!(char!('@'), parse_label_name), |name| Expr::Label(name.to_string())) |
+        map!(parse_identifier, |name| Expr::Constant(name.to_string())) |
+        delimited!(char!('('), parse_expression, char!(')')) |
+        map!(preceded!(char!('-'), parse_factor), |expr| Expr::Negate(Box::new(expr)))
+    )
+);
+
+/// Parser for the name of a constant, such as `BUF_SIZE` or `buf.size`
+named!(pub parse_identifier<CompleteStr, CompleteStr>,
+    recognize!(
+        pair!(
+            alt!(alpha1 | tag!("_")),
+            take_while!(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        )
+    )
+);
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary(op, Box::new(left), Box::new(right))
+}
+
+named!(parse_hex_literal<CompleteStr, i64>,
+    preceded!(
+        alt!(tag!("0x") | tag!("0X")),
+        map_res!(hex_digit, |d: CompleteStr| i64::from_str_radix(&d, 16))
+    )
+);
+
+named!(parse_binary_literal<CompleteStr, i64>,
+    preceded!(
+        alt!(tag!("0b") | tag!("0B")),
+        map_res!(is_a!("01"), |d: CompleteStr| i64::from_str_radix(&d, 2))
+    )
+);
+
+named!(parse_decimal_literal<CompleteStr, i64>,
+    map_res!(digit, |d: CompleteStr| d.parse::<i64>())
+);
+
+/// Parser for a single character such as `'a'` or `'\n'`, which evaluates to its code point
+named!(parse_char_literal<CompleteStr, i64>,
+    map!(
+        delimited!(
+            char!('\''),
+            alt!(
+                preceded!(char!('\\'), map_opt!(anychar, unescape)) |
+                none_of!("\\'")
+            ),
+            char!('\'')
+        ),
+        |c: char| i64::from(c as u32)
+    )
+);
+
+/// Maps the character after a backslash to the character it stands for
+fn unescape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' => Some('\\'),
+        '\'' => Some('\''),
+        _ => None,
+    }
+}
+
 /// Parser for vm's string constants
 named!(parse_irstring<CompleteStr, Token>,
     do_parse!(
@@ -40,33 +186,49 @@ named!(parse_irstring<CompleteStr, Token>,
     )
 );
 
+/// Parser for float numbers such as `#3.14`, `#-0.5` or `#6.02e23`.
+/// A literal needs a fraction or an exponent, otherwise it is an integer.
 named!(parse_float_operand<CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
             tag!("#") >>
-            sign: opt!(tag!("-")) >>
-            left_nums: digit >>
-            tag!(".") >>
-            right_nums: digit >>
+            value: parse_float_literal >>
             (
-                {
-                    let mut num = String::from("");
-                    if sign.is_some() {
-                        num.push_str("-");
-                    }
-                    num.push_str(&left_nums.to_string());
-                    num.push_str(".");
-                    num.push_str(&right_nums.to_string());
-                    Token::FloatOperand{value: num.parse::<f64>().unwrap()}
-                }
+                Token::FloatOperand{value}
             )
         )
     )
 );
 
+named!(parse_float_literal<CompleteStr, f64>,
+    map_res!(
+        recognize!(
+            do_parse!(
+                opt!(tag!("-")) >>
+                digit >>
+                alt!(
+                    do_parse!(tag!(".") >> digit >> opt!(parse_exponent) >> ()) |
+                    parse_exponent
+                ) >>
+                ()
+            )
+        ),
+        |s: CompleteStr| s.parse::<f64>()
+    )
+);
+
+named!(parse_exponent<CompleteStr, ()>,
+    do_parse!(
+        alt!(tag!("e") | tag!("E")) >>
+        opt!(alt!(tag!("+") | tag!("-"))) >>
+        digit >>
+        ()
+    )
+);
+
 #[cfg(test)]
 mod tests {
-    #![allow(unused_imports)]
+    #![allow(unused_imports, clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -82,6 +244,27 @@ mod tests {
         assert_eq!(result.is_ok(), false);
     }
 
+    #[test]
+    fn test_parse_integer_literals() {
+        let tests = [
+            ("#-1", -1),
+            ("#70000", 70000),
+            ("#0xFF", 255),
+            ("#-0x10", -16),
+            ("#0b1010", 10),
+            ("#'a'", 97),
+            ("#'\\n'", 10),
+            ("#'\\''", 39),
+        ];
+        for &(input, value) in tests.iter() {
+            let result = parse_integer_operand(CompleteStr(input));
+            assert_eq!(result, Ok((CompleteStr(""), Token::IntegerOperand { value })));
+        }
+
+        assert_eq!(parse_integer_operand(CompleteStr("#''")).is_ok(), false);
+        assert_eq!(parse_integer_operand(CompleteStr("#'\\q'")).is_ok(), false);
+    }
+
     #[test]
     fn test_parse_string_operand() {
         let result = parse_irstring(CompleteStr("'This is a test'"));
@@ -97,5 +280,32 @@ mod tests {
         for i in &test {
             assert_eq!(parse_float_operand(CompleteStr(i)).is_ok(), true);
         }
+
+        let tests = [("#2.75", 2.75), ("#-2.5e-3", -2.5e-3), ("#6E2", 600.0), ("#1.5e+2", 150.0)];
+        for &(input, value) in tests.iter() {
+            let result = parse_operand(CompleteStr(input));
+            assert_eq!(result, Ok((CompleteStr(""), Token::FloatOperand { value })));
+        }
+
+        assert_eq!(parse_float_operand(CompleteStr("#3")).is_ok(), false);
+        assert_eq!(parse_operand(CompleteStr("#3")), Ok((CompleteStr(""), Token::IntegerOperand { value: 3 })));
+    }
+
+    #[test]
+    fn test_parse_expression_operand() {
+        let tests = [("#2+3*4", 14), ("#(2+3)*4", 20), ("#-(1-3)", 2), ("#0x10%3", 1), ("#'a'+1", 98)];
+        for &(input, value) in tests.iter() {
+            assert_eq!(parse_operand(CompleteStr(input)), Ok((CompleteStr(""), Token::IntegerOperand { value })));
+        }
+
+        let (rest, token) = parse_operand(CompleteStr("#BUF_SIZE*2 $1")).unwrap();
+        assert_eq!(rest, CompleteStr("$1"));
+        let expected = Expr::Binary(BinaryOp::Mul, Box::new(Expr::Constant("BUF_SIZE".to_string())), Box::new(Expr::Number(2)));
+        assert_eq!(token, Token::Expression { expr: expected });
+
+        let (_, token) = parse_operand(CompleteStr("@table+len(@msg)")).unwrap();
+        let expected = Expr::Binary(BinaryOp::Add, Box::new(Expr::Label("table".to_string())), Box::new(Expr::Len("msg".to_string())));
+        assert_eq!(token, Token::Expression { expr: expected });
+        assert_eq!(parse_operand(CompleteStr("@table")), Ok((CompleteStr(""), Token::LabelUsage { name: "table".to_string() })));
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,9 @@
+use assembler::comment_parsers::skip_space;
 use assembler::directive_parsers::parse_directive;
+use assembler::errors::AssemblerError;
 use assembler::instruction_parsers::*;
+use assembler::pseudo::expand_pseudo_instruction;
+use assembler::source::SourceLocation;
 use assembler::symbols::SymbolTable;
 use nom::types::CompleteStr;
 
@@ -12,54 +16,134 @@ pub struct Program {
 
 impl Program {
     /// Translates instruction into bytes for eval.
-    pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
+    /// Pseudo-instructions are expanded here, since eval doesn't go through the assembler's passes
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Result<Vec<u8>, AssemblerError> {
         let mut program = vec![];
         for instr in &self.instructions {
-            program.append(&mut instr.to_bytes(symbols));
+            for real in expand_pseudo_instruction(instr.clone())? {
+                program.append(&mut real.to_bytes(symbols)?);
+            }
         }
 
-        program
+        Ok(program)
+    }
+
+    /// Turns the positions recorded while parsing `source` into lines and columns
+    fn resolve_locations(&mut self, source: &str, file: &str) {
+        for instr in &mut self.instructions {
+            instr.resolve_locations(source, file);
+        }
     }
 }
 
-/// parse the program to a vector of instructions
-named!(pub parse_program<CompleteStr, Program>,
-    do_parse!(
-        instructions: many1!(alt!(parse_instruction | parse_directive)) >>
-        (
-            Program {
-                instructions: instructions
-            }
-        )
-    )
+/// One instruction or directive
+named!(parse_line<CompleteStr, AssemblerInstruction>,
+    alt!(parse_instruction | parse_directive)
 );
 
+/// Parses a whole program, reading `file` only to report errors against.
+/// A line that does not parse is reported and skipped, so every syntax error of the source
+/// comes back at once; the program is only returned when there were none
+pub fn parse_program(source: &str, file: &str) -> Result<Program, Vec<AssemblerError>> {
+    let mut instructions = vec![];
+    let mut errors = vec![];
+    let mut rest = skip_space(source);
+    while !rest.is_empty() {
+        match parse_line(CompleteStr(rest)) {
+            Ok((next, instruction)) if next.len() < rest.len() => {
+                instructions.push(instruction);
+                let consumed = &rest[..rest.len() - next.len()];
+                let after = skip_space(next.0);
+
+                // Whatever follows an instruction on the same line was not understood
+                let gap = &rest[consumed.trim_end().len()..rest.len() - after.len()];
+                if !after.is_empty() && !gap.contains('\n') {
+                    errors.push(syntax_error("Unexpected input after the instruction", after));
+                    rest = skip_space(skip_line(after));
+                } else {
+                    rest = after;
+                }
+            }
+            _ if rest.starts_with("/*") => {
+                errors.push(syntax_error("Block comment is never closed", rest));
+                rest = "";
+            }
+            _ => {
+                errors.push(syntax_error("Expected an instruction or a directive", rest));
+                rest = skip_space(skip_line(rest));
+            }
+        }
+    }
+
+    let mut program = Program { instructions };
+    program.resolve_locations(source, file);
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        for error in &mut errors {
+            if let AssemblerError::ParseError { ref mut location, .. } = error {
+                location.resolve(source, file);
+            }
+        }
+        Err(errors)
+    }
+}
+
+/// A syntax error covering what is left of the line at `rest`
+fn syntax_error(message: &str, rest: &str) -> AssemblerError {
+    let line = rest.lines().next().unwrap_or("");
+    AssemblerError::ParseError {
+        error: message.to_string(),
+        location: SourceLocation::between(rest, &rest[line.len()..]),
+    }
+}
+
+/// Everything after the line `rest` starts on
+fn skip_line(rest: &str) -> &str {
+    match rest.find('\n') {
+        Some(end) => &rest[end + 1..],
+        None => "",
+    }
+}
+
 
 mod tests {
-    #![allow(unused_imports)]
+    #![allow(unused_imports, clippy::bool_assert_comparison)]
 
     use super::*;
+    use instruction::Opcode;
 
     #[test]
     fn test_parse_program() {
-        let result = parse_program(CompleteStr("load $0 #100\n"));
+        let result = parse_program("load $0 #100\n", "test.iasm");
         assert_eq!(result.is_ok(), true);
-        let (leftover, p) = result.unwrap();
-        assert_eq!(leftover, CompleteStr(""));
+        let p = result.unwrap();
         assert_eq!(1, p.instructions.len());
+        assert_eq!(p.instructions[0].locations.opcode.file.as_ref(), "test.iasm");
     }
 
     #[test]
     fn test_program_to_bytes() {
-        let result = parse_program(CompleteStr("load $0 #100\n"));
+        let result = parse_program("load $0 #100\n", "test.iasm");
         assert_eq!(result.is_ok(), true);
-        let (_, p) = result.unwrap();
+        let p = result.unwrap();
         let symbols = SymbolTable::new();
-        let bytecode = p.to_bytes(&symbols);
+        let bytecode = p.to_bytes(&symbols).unwrap();
         assert_eq!(bytecode.len(), 4);
         println!("{:?}", bytecode);
     }
 
+    #[test]
+    fn test_pseudo_program_to_bytes() {
+        let p = parse_program("beq $1 $2 #80
+", "test.iasm").unwrap();
+        let bytecode = p.to_bytes(&SymbolTable::new()).unwrap();
+        assert_eq!(bytecode, vec![u8::from(Opcode::EQ), 1, 2, 0, u8::from(Opcode::DJMPE), 0, 80, 0]);
+        let p = parse_program("clr $1 $2
+", "test.iasm").unwrap();
+        assert_eq!(p.to_bytes(&SymbolTable::new()).unwrap_err().to_string(), "`clr` expects a register");
+    }
+
     #[test]
     fn test_complete_program() {
         let program = CompleteStr(r"
@@ -67,9 +151,62 @@ mod tests {
         hello: .asciiz 'Hello everyone!'
         .code
         hlt");
-        let result = parse_program(program);
+        let result = parse_program(&program, "test.iasm");
         assert_eq!(result.is_ok(), true);
-        let (rest, _) = result.unwrap();
-        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(result.unwrap().instructions.len(), 4);
+    }
+
+    #[test]
+    fn test_report_every_syntax_error() {
+        let program = ".data\n.code\n!!!\nload $0 #1\nhlt hlt\n%%\n";
+        let errors = parse_program(program, "test.iasm").unwrap_err();
+        assert_eq!(errors.len(), 3);
+        let lines: Vec<(usize, usize)> = errors
+            .iter()
+            .map(|e| (e.location().line, e.location().column))
+            .collect();
+        assert_eq!(lines, vec![(3, 1), (5, 5), (6, 1)]);
+        assert_eq!(errors[1].location().end - errors[1].location().start, 3);
+    }
+
+    #[test]
+    fn test_comments() {
+        let program = r"
+        ; a line comment before anything
+        .data   ; the data section
+        hello: .asciiz 'Hello; world' /* a ; inside a string is not a comment */
+        .code
+        /* a block comment
+           over several lines */
+        load $0 /* between operands */ #100 ; after the operands
+        loop: ; a label on its own line
+        hlt";
+        let p = parse_program(program, "test.iasm").unwrap();
+        assert_eq!(p.instructions.len(), 5);
+        assert_eq!(p.instructions[1].get_string_constant(), Some("Hello; world".to_string()));
+
+        let load = &p.instructions[3].locations;
+        assert_eq!((load.instruction.line, load.instruction.column), (8, 9));
+        assert_eq!(load.instruction.end - load.instruction.start, 35);
+        assert_eq!((load.operands[1].line, load.operands[1].column), (8, 40));
+        assert_eq!(load.operands[1].end - load.operands[1].start, 4);
+
+        let hlt = &p.instructions[4].locations;
+        assert_eq!((hlt.label.line, hlt.label.column), (9, 9));
+        assert_eq!(hlt.label.end - hlt.label.start, 5);
+        assert_eq!((hlt.opcode.line, hlt.opcode.column), (10, 9));
+    }
+
+    #[test]
+    fn test_unclosed_block_comment() {
+        let errors = parse_program(".data\n.code\nhlt\n/* oops\nhlt\n", "test.iasm").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location().line, 4);
+    }
+
+    #[test]
+    fn test_empty_program() {
+        let result = parse_program("  \n\n", "test.iasm");
+        assert_eq!(result.unwrap().instructions.len(), 0);
     }
 }
\ No newline at end of file
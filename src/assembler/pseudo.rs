@@ -0,0 +1,133 @@
+use assembler::errors::AssemblerError;
+use assembler::instruction_parsers::AssemblerInstruction;
+use assembler::Token;
+use instruction::Opcode;
+
+/// Mnemonics the assembler accepts but the VM doesn't, with what each one expects
+const PSEUDO_INSTRUCTIONS: &[(&str, &str)] = &[
+    ("beq", "two registers and a branch target"),
+    ("bne", "two registers and a branch target"),
+    ("blt", "two registers and a branch target"),
+    ("bgt", "two registers and a branch target"),
+    ("ble", "two registers and a branch target"),
+    ("bge", "two registers and a branch target"),
+    ("li", "a register and an integer"),
+    ("clr", "a register"),
+];
+
+/// Whether `name` is a pseudo-instruction, which a macro can't be named after
+pub fn is_pseudo_instruction(name: &str) -> bool {
+    PSEUDO_INSTRUCTIONS.iter().any(|(pseudo, _)| *pseudo == name)
+}
+
+/// The real instructions a pseudo-instruction stands for, or `inst` itself if it is not one.
+/// A label on the pseudo-instruction names the first of them, and they all point back at its source
+pub fn expand_pseudo_instruction(inst: AssemblerInstruction) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+    let (name, expected) = match inst.opcode {
+        Some(Token::UnknownOp { ref name }) => match PSEUDO_INSTRUCTIONS.iter().find(|(pseudo, _)| pseudo == name) {
+            Some(&pseudo) => pseudo,
+            None => return Ok(vec![inst]),
+        },
+        _ => return Ok(vec![inst]),
+    };
+    let wrong_operands = || AssemblerError::WrongPseudoOperands {
+        name: name.to_string(),
+        expected,
+        location: inst.locations.instruction.clone(),
+    };
+
+    let operands = inst.operands();
+    let registers = operands.iter().take_while(|(token, _)| is_register(token)).count();
+    match name {
+        "li" if operands.len() == 2 && registers == 1 => Ok(vec![with_opcode(&inst, Opcode::LOAD)]),
+        "clr" if operands.len() == 1 && registers == 1 => {
+            let mut load = with_opcode(&inst, Opcode::LOAD);
+            load.operand2 = Some(Token::IntegerOperand { value: 0 });
+            load.locations.operands[1] = inst.locations.operands[0].clone();
+            Ok(vec![load])
+        }
+        "li" | "clr" => Err(wrong_operands()),
+        _ if operands.len() == 3 && registers == 2 => {
+            // Compare into the equality flag, then jump if it is set
+            let compare = match name {
+                "beq" => Opcode::EQ,
+                "bne" => Opcode::NEQ,
+                "blt" => Opcode::LT,
+                "bgt" => Opcode::GT,
+                "ble" => Opcode::LTE,
+                _ => Opcode::GTE,
+            };
+            let mut test = with_opcode(&inst, compare);
+            test.operand3 = None;
+            let mut jump = with_opcode(&inst, Opcode::DJMPE);
+            jump.label = None;
+            jump.operand1 = inst.operand3.clone();
+            jump.operand2 = None;
+            jump.operand3 = None;
+            jump.locations.operands[0] = inst.locations.operands[2].clone();
+            Ok(vec![test, jump])
+        }
+        _ => Err(wrong_operands()),
+    }
+}
+
+fn is_register(token: &Token) -> bool {
+    matches!(token, Token::Register { .. })
+}
+
+/// A copy of `inst` with a real opcode in place of the pseudo one
+fn with_opcode(inst: &AssemblerInstruction, code: Opcode) -> AssemblerInstruction {
+    let mut real = inst.clone();
+    real.opcode = Some(Token::Op { code });
+    real
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::program_parsers::parse_program;
+    use assembler::source::DEFAULT_FILE_NAME;
+
+    fn expand(source: &str) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+        let program = parse_program(source, DEFAULT_FILE_NAME).unwrap();
+        expand_pseudo_instruction(program.instructions.into_iter().next().unwrap())
+    }
+
+    #[test]
+    fn test_expand_branch() {
+        let expanded = expand("loop: blt $1 $2 @loop").unwrap();
+        assert_eq!(expanded.len(), 2);
+        assert_eq!(expanded[0].get_label_name(), Some("loop".to_string()));
+        assert_eq!(expanded[0].opcode, Some(Token::Op { code: Opcode::LT }));
+        assert_eq!(expanded[0].operands().len(), 2);
+        assert_eq!(expanded[1].label, None);
+        assert_eq!(expanded[1].opcode, Some(Token::Op { code: Opcode::DJMPE }));
+        assert_eq!(expanded[1].operand1, Some(Token::LabelUsage { name: "loop".to_string() }));
+        assert_eq!(expanded[1].locations.operands[0].column, 17);
+    }
+
+    #[test]
+    fn test_expand_load_aliases() {
+        let expanded = expand("li $3 #70000").unwrap();
+        assert_eq!(expanded[0].opcode, Some(Token::Op { code: Opcode::LOAD }));
+        assert_eq!(expanded[0].size(), 8);
+
+        let expanded = expand("clr $3").unwrap();
+        assert_eq!(expanded[0].operand2, Some(Token::IntegerOperand { value: 0 }));
+
+        let expanded = expand("mov $1 $2").unwrap();
+        assert_eq!(expanded[0].opcode, Some(Token::Op { code: Opcode::MOV }));
+    }
+
+    #[test]
+    fn test_pseudo_operand_errors() {
+        for source in &["beq $1 @end", "bne $1 #2 @end", "li #1 $2", "clr $1 $2"] {
+            let error = expand(source).unwrap_err();
+            assert_eq!(error.location().column, 1);
+        }
+        assert_eq!(
+            expand("beq $1 @end").unwrap_err().to_string(),
+            "`beq` expects two registers and a branch target"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use assembler::errors::AssemblerError;
+use assembler::instruction_parsers::AssemblerInstruction;
+use assembler::program_parsers::Program;
+use assembler::register_parsers::{builtin_register, RegisterFile};
+use assembler::Token;
+use std::collections::HashMap;
+use vm::REGISTER_COUNT;
+
+/// Replaces the aliases declared with `.reg name $n` with their registers.
+/// An alias can be used after it is declared, and the `.reg` directives are taken out,
+/// so the two passes never see them
+pub fn resolve_register_aliases(program: Program) -> Result<Program, Vec<AssemblerError>> {
+    let mut aliases: HashMap<String, (u8, Option<RegisterFile>)> = HashMap::new();
+    let mut errors = vec![];
+    let mut instructions = vec![];
+    for mut inst in program.instructions {
+        for (token, location) in inst.operands_mut() {
+            if let Token::RegisterAlias { name } = token.clone() {
+                match aliases.get(&name) {
+                    Some(&(reg_num, file)) => *token = Token::Register { reg_num, file },
+                    None => errors.push(AssemblerError::UnknownRegister { name, location: location.clone() }),
+                }
+            }
+        }
+
+        if inst.get_directive_name().as_deref() != Some("reg") {
+            instructions.push(inst);
+            continue;
+        }
+        if let Err(e) = declare(&inst, &mut aliases) {
+            errors.push(e);
+        }
+        // A label on the directive names what follows it
+        if let Some(label) = inst.label.clone() {
+            let mut label_only =
+                AssemblerInstruction::from_located(Some((label, inst.locations.label.clone())), None, None, [None, None, None]);
+            label_only.locations.instruction = inst.locations.instruction.clone();
+            instructions.push(label_only);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Program { instructions })
+    } else {
+        Err(errors)
+    }
+}
+
+/// Records the alias declared by `.reg name $n`
+fn declare(inst: &AssemblerInstruction, aliases: &mut HashMap<String, (u8, Option<RegisterFile>)>) -> Result<(), AssemblerError> {
+    let operands = inst.operands();
+    let (name, name_location, reg_num, file, location) = match operands.as_slice() {
+        [(Token::Name { name }, name_location), (Token::Register { reg_num, file }, location)] => {
+            (name, name_location, *reg_num, *file, location)
+        }
+        // An alias that is not declared yet was already reported
+        [(Token::Name { .. }, _), (Token::RegisterAlias { .. }, _)] => return Ok(()),
+        _ => {
+            return Err(AssemblerError::WrongDirectiveOperand {
+                directive: "reg".to_string(),
+                expected: "a name and a register such as $5",
+                location: operands.get(1).map_or(&inst.locations.directive, |o| o.1).clone(),
+            })
+        }
+    };
+
+    if reg_num as usize >= REGISTER_COUNT {
+        return Err(AssemblerError::RegisterOutOfRange { register: reg_num, location: (*location).clone() });
+    }
+    // The names of the calling convention can't be changed
+    if builtin_register(name).is_some() || aliases.contains_key(name) {
+        return Err(AssemblerError::SymbolAlreadyDeclared { name: name.clone(), location: (*name_location).clone() });
+    }
+    // An alias of `$f1` is a float register like it
+    aliases.insert(name.clone(), (reg_num, file));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::bool_assert_comparison)]
+
+    use super::*;
+    use assembler::source::DEFAULT_FILE_NAME;
+    use assembler::program_parsers::parse_program;
+
+    fn resolve(source: &str) -> Result<Program, Vec<AssemblerError>> {
+        resolve_register_aliases(parse_program(source, DEFAULT_FILE_NAME).unwrap())
+    }
+
+    #[test]
+    fn test_resolve_aliases() {
+        let program = resolve(".reg count $t0\n.reg total $12\nstart: .reg copy $count\nadd $count $copy $total\n").unwrap();
+        assert_eq!(program.instructions.len(), 2);
+        assert_eq!(program.instructions[0].get_label_name(), Some("start".to_string()));
+        assert_eq!(program.instructions[0].is_directive(), false);
+        let registers: Vec<&Token> = program.instructions[1].operands().into_iter().map(|(token, _)| token).collect();
+        assert_eq!(
+            registers,
+            vec![
+                &Token::Register { reg_num: 7, file: Some(RegisterFile::Int) },
+                &Token::Register { reg_num: 7, file: Some(RegisterFile::Int) },
+                &Token::Register { reg_num: 12, file: None }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_alias_errors() {
+        let errors = resolve("inc $count\n.reg count $1\n.reg count $2\n.reg sp $3\n.reg big $40\n.reg $4\n").unwrap_err();
+        assert_eq!(errors.len(), 5);
+        assert_eq!(errors[0].to_string(), "Register $count was never declared with .reg");
+        assert_eq!((errors[0].location().line, errors[0].location().column), (1, 5));
+        assert_eq!(errors[1].to_string(), "Symbol count was previously declared.");
+        assert_eq!(errors[2].to_string(), "Symbol sp was previously declared.");
+        assert_eq!(errors[3].to_string(), "Register $40 does not exist, registers go from $0 to $31");
+        assert_eq!(errors[4].to_string(), "`.reg` expects a name and a register such as $5");
+    }
+}
@@ -1,18 +1,67 @@
+use assembler::operand_parsers::parse_identifier;
 use assembler::Token;
 use nom::digit;
 use nom::types::CompleteStr;
 
-/// Parser for register number, which we use `$` as prefix
+/// Which of the VM's two sets of registers a register name belongs to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterFile {
+    Int,
+    Float,
+}
+
+/// Groups of numbered register names in the calling convention: the prefix, the first register,
+/// how many there are and the registers they are in
+const NUMBERED_REGISTERS: &[(&str, u8, u8, RegisterFile)] = &[
+    // Return values
+    ("v", 1, 2, RegisterFile::Int),
+    // Arguments
+    ("a", 3, 4, RegisterFile::Int),
+    // Temporaries, which calls may change
+    ("t", 7, 12, RegisterFile::Int),
+    // Saved registers, which calls have to keep
+    ("s", 19, 10, RegisterFile::Int),
+    // Float registers, which are numbered on their own
+    ("f", 0, 32, RegisterFile::Float),
+];
+
+/// The register a name of the calling convention stands for, such as `sp` for `$30` or `a0` for `$3`
+pub fn builtin_register(name: &str) -> Option<(u8, RegisterFile)> {
+    match name {
+        "zero" => return Some((0, RegisterFile::Int)),
+        "fp" => return Some((29, RegisterFile::Int)),
+        "sp" => return Some((30, RegisterFile::Int)),
+        "ra" => return Some((31, RegisterFile::Int)),
+        _ => {}
+    }
+    let (prefix, index) = name.split_at(name.chars().next()?.len_utf8());
+    // `$t01` is not `$t1`
+    if index.is_empty() || (index.len() > 1 && index.starts_with('0')) {
+        return None;
+    }
+    let index = index.parse::<u8>().ok()?;
+    NUMBERED_REGISTERS
+        .iter()
+        .find(|&&(group, _, count, _)| group == prefix && index < count)
+        .map(|&(_, first, _, file)| (first + index, file))
+}
+
+/// Parser for register number, which we use `$` as prefix.
+/// A name after the `$` is either one of the calling convention, which keeps the registers it is in,
+/// or an alias declared with `.reg`
 named!(pub parse_register<CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
             tag!("$") >>
-            reg_num: digit >>
-            (
-                Token::Register{
-                    reg_num: reg_num.parse::<u8>().unwrap()
-                }
-            )
+            // Registers that exist are checked later, against the opcode using them
+            register: alt!(
+                map_res!(digit, |d: CompleteStr| d.parse::<u8>().map(|reg_num| Token::Register { reg_num, file: None })) |
+                map!(parse_identifier, |name: CompleteStr| match builtin_register(&name) {
+                    Some((reg_num, file)) => Token::Register { reg_num, file: Some(file) },
+                    None => Token::RegisterAlias { name: name.to_string() },
+                })
+            ) >>
+            (register)
         )
     )
 );
@@ -20,7 +69,7 @@ named!(pub parse_register<CompleteStr, Token>,
 
 #[cfg(test)]
 mod tests {
-    #![allow(unused_imports)]
+    #![allow(unused_imports, clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -30,7 +79,27 @@ mod tests {
         assert_eq!(res.is_ok(), true);
         let res = parse_register(CompleteStr("0"));
         assert_eq!(res.is_ok(), false);
-        let res = parse_register(CompleteStr("$a"));
+        let res = parse_register(CompleteStr("$"));
+        assert_eq!(res.is_ok(), false);
+        let res = parse_register(CompleteStr("$300"));
         assert_eq!(res.is_ok(), false);
+        // Names that are not part of the calling convention are left for `.reg` to define
+        let res = parse_register(CompleteStr("$a"));
+        assert_eq!(res, Ok((CompleteStr(""), Token::RegisterAlias { name: "a".to_string() })));
+    }
+
+    #[test]
+    fn test_parse_register_alias() {
+        let tests = [("$zero", 0), ("$v1", 2), ("$a0", 3), ("$a3", 6), ("$t0", 7), ("$t11", 18), ("$s9", 28), ("$fp", 29), ("$sp", 30), ("$ra", 31)];
+        for &(input, reg_num) in tests.iter() {
+            let file = Some(RegisterFile::Int);
+            assert_eq!(parse_register(CompleteStr(input)), Ok((CompleteStr(""), Token::Register { reg_num, file })));
+        }
+        let file = Some(RegisterFile::Float);
+        assert_eq!(parse_register(CompleteStr("$f31")), Ok((CompleteStr(""), Token::Register { reg_num: 31, file })));
+        assert_eq!(parse_register(CompleteStr("$31")), Ok((CompleteStr(""), Token::Register { reg_num: 31, file: None })));
+        for name in &["a4", "t12", "f32", "t01", "s"] {
+            assert_eq!(builtin_register(name), None);
+        }
     }
 }
@@ -0,0 +1,113 @@
+use assembler::comment_parsers::skip_space;
+use nom::types::CompleteStr;
+use nom::IResult;
+use std::fmt;
+use std::rc::Rc;
+
+/// The name errors use for source that was not read from a file
+pub const DEFAULT_FILE_NAME: &str = "<input>";
+
+/// Where in the source a token, an instruction or an error is.
+///
+/// Parsers only see the input that is left, so they store `start` and `end` as the amount of
+/// input remaining at each end of the match. `resolve` turns those into byte offsets,
+/// a line and a column once the whole source is known.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceLocation {
+    /// The file the source was read from, shared by every location in it
+    pub file: Rc<str>,
+    /// Line of the start of the span, starting at 1
+    pub line: usize,
+    /// Column of the start of the span in characters, starting at 1
+    pub column: usize,
+    /// Byte offset where the span starts
+    pub start: usize,
+    /// Byte offset right after the span
+    pub end: usize,
+    /// The whole line the span starts on
+    pub source_line: String,
+    /// The macro call this span was copied into, for spans in a macro's body
+    pub expansion: Option<Box<MacroExpansion>>,
+}
+
+/// A call of a macro, which its body was expanded at
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacroExpansion {
+    pub name: String,
+    pub call: SourceLocation,
+}
+
+impl SourceLocation {
+    /// Marks what a parser consumed going from `input` to `rest`, without the surrounding whitespace
+    pub fn between(input: &str, rest: &str) -> Self {
+        let matched = &input[..input.len() - rest.len()];
+        let leading = matched.len() - matched.trim_start().len();
+        let start = input.len() - leading;
+        Self {
+            start,
+            end: start - matched.trim().len(),
+            ..Default::default()
+        }
+    }
+
+    /// Turns the remaining lengths recorded by the parsers into a position in `source`
+    pub fn resolve(&mut self, source: &str, file: &str) {
+        let start = source.len().saturating_sub(self.start);
+        let end = source.len().saturating_sub(self.end);
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+
+        self.file = Rc::from(file);
+        self.line = source[..start].matches('\n').count() + 1;
+        self.column = source[line_start..start].chars().count() + 1;
+        self.start = start;
+        self.end = end;
+        self.source_line = source[line_start..].lines().next().unwrap_or("").to_string();
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Runs `parser` and also returns where its match is.
+/// Leading whitespace and comments are skipped first so they are not part of the match
+pub fn with_location<'a, T>(
+    input: CompleteStr<'a>,
+    parser: fn(CompleteStr<'a>) -> IResult<CompleteStr<'a>, T>,
+) -> IResult<CompleteStr<'a>, (T, SourceLocation)> {
+    let input = CompleteStr(skip_space(input.0));
+    let (rest, value) = parser(input)?;
+    let location = SourceLocation::between(input.0, rest.0);
+    Ok((rest, (value, location)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_location() {
+        let source = "load $0 #1\n  add $0 $1 $2\n";
+        let input = &source[11..];
+        let mut location = SourceLocation::between(input, &input[6..]);
+        location.resolve(source, "test.iasm");
+        assert_eq!(location.line, 2);
+        assert_eq!(location.column, 3);
+        assert_eq!(location.start, 13);
+        assert_eq!(location.end, 16);
+        assert_eq!(location.source_line, "  add $0 $1 $2");
+        assert_eq!(location.to_string(), "test.iasm:2:3");
+    }
+
+    #[test]
+    fn test_empty_location() {
+        let source = "hlt";
+        let mut location = SourceLocation::between(source, source);
+        location.resolve(source, DEFAULT_FILE_NAME);
+        assert_eq!(location.line, 1);
+        assert_eq!(location.column, 1);
+        assert_eq!(location.start, location.end);
+    }
+}
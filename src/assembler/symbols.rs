@@ -1,95 +1,247 @@
-#[derive(Debug)]
+use assembler::source::SourceLocation;
+use linker::object::Section;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A name declared in the source, and what the assembler knows about it
+#[derive(Debug, Clone)]
 pub struct Symbol {
     name: String,
     offset: Option<u32>,
     symbol_type: SymbolType,
+    /// The value of a constant declared with `.equ`
+    value: Option<i64>,
+    /// How many bytes of data a data label names, what `len(@label)` evaluates to
+    size: Option<u32>,
+    visibility: Visibility,
+    /// Where the symbol was declared, if it was declared in the source
+    location: Option<SourceLocation>,
 }
 
 impl Symbol {
     pub fn new(name: String, symbol_type: SymbolType) -> Self {
-        Self { name, symbol_type, offset: None }
+        // Another object defines an external symbol, so it has to be global there
+        let visibility = if symbol_type == SymbolType::External { Visibility::Global } else { Visibility::Local };
+        Self { name, symbol_type, offset: None, value: None, size: None, visibility, location: None }
     }
 
     pub fn new_with_offset(name: String, symbol_type: SymbolType, offset: u32) -> Self {
-        Self {
-            name,
-            symbol_type,
-            offset: Some(offset),
+        Self { offset: Some(offset), ..Self::new(name, symbol_type) }
+    }
+
+    /// A constant declared with `.equ NAME #value`
+    pub fn new_constant(name: String, value: i64) -> Self {
+        Self { value: Some(value), ..Self::new(name, SymbolType::Constant) }
+    }
+
+    /// Records where the symbol was declared
+    pub fn with_location(mut self, location: SourceLocation) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The kind of thing this symbol names
+    pub fn symbol_type(&self) -> &SymbolType {
+        &self.symbol_type
+    }
+
+    /// The address of a code label, or the read-only offset of a data label
+    pub fn offset(&self) -> Option<u32> {
+        self.offset
+    }
+
+    /// The value of a constant
+    pub fn value(&self) -> Option<i64> {
+        self.value
+    }
+
+    /// How many bytes of data a data label names
+    pub fn size(&self) -> Option<u32> {
+        self.size
+    }
+
+    /// The section a label points into, which says how linking moves it
+    pub fn section(&self) -> Option<Section> {
+        match self.symbol_type {
+            SymbolType::CodeLabel => Some(Section::Code),
+            SymbolType::DataLabel => Some(Section::ReadOnly),
+            SymbolType::Constant | SymbolType::External => None,
         }
     }
+
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    pub fn location(&self) -> Option<&SourceLocation> {
+        self.location.as_ref()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SymbolType {
-    Label,
+    /// A label in `.code`, whose value is an absolute address
+    CodeLabel,
+    /// A label in `.data`, whose value is an offset in the read-only section
+    DataLabel,
+    Constant,
+    /// A label another object defines, declared with `.extern`
+    External,
+}
+
+impl fmt::Display for SymbolType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SymbolType::CodeLabel => "code label",
+            SymbolType::DataLabel => "data label",
+            SymbolType::Constant => "constant",
+            SymbolType::External => "external",
+        })
+    }
+}
+
+/// Whether other objects can use a symbol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Visibility {
+    Local,
+    /// Exported with `.global`, or defined by another object
+    Global,
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Visibility::Local => "local",
+            Visibility::Global => "global",
+        })
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct SymbolTable {
-    // todo: use HashTable to replace Vec
-    pub symbols: Vec<Symbol>,
+    /// The symbols in the order they were declared
+    symbols: Vec<Symbol>,
+    /// Where each symbol is in `symbols`, by name
+    index: HashMap<String, usize>,
+    /// Read-only section offsets of the float constants used as immediates, keyed by their bits
+    float_constants: HashMap<u64, u32>,
 }
 
 impl SymbolTable {
     /// Creates a symbol table
     pub fn new() -> Self {
-        Self { symbols: Vec::new() }
+        Self::default()
+    }
+
+    /// Records where a float constant was stored in the read-only section
+    pub fn add_float_constant(&mut self, value: f64, offset: u32) {
+        self.float_constants.insert(value.to_bits(), offset);
+    }
+
+    /// Given a float constant, return its offset in the read-only section
+    pub fn float_constant_offset(&self, value: f64) -> Option<u32> {
+        self.float_constants.get(&value.to_bits()).cloned()
     }
 
-    /// Add a symbol to table
-    pub fn add_symbol(&mut self, s: Symbol) {
+    /// Add a symbol to table.
+    /// Returns false, keeping the symbol already there, if the name is taken
+    pub fn add_symbol(&mut self, s: Symbol) -> bool {
+        if self.index.contains_key(&s.name) {
+            return false;
+        }
+        self.index.insert(s.name.clone(), self.symbols.len());
         self.symbols.push(s);
+        true
     }
 
-    pub fn has_symbol(&self, s: &str) -> bool {
-        for symbol in &self.symbols {
-            if symbol.name == s {
-                return true;
-            }
+    /// Given a name, return its symbol
+    pub fn get(&self, s: &str) -> Option<&Symbol> {
+        self.index.get(s).map(|&i| &self.symbols[i])
+    }
+
+    fn get_mut(&mut self, s: &str) -> Option<&mut Symbol> {
+        match self.index.get(s) {
+            Some(&i) => Some(&mut self.symbols[i]),
+            None => None,
         }
+    }
+
+    /// The symbols in the order they were declared
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
 
-        false
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn has_symbol(&self, s: &str) -> bool {
+        self.index.contains_key(s)
     }
 
     /// Gived a s, return its mapping value
     pub fn symbol_value(&self, s: &str) -> Option<u32> {
-        for symbol in &self.symbols {
-            if symbol.name == s {
-                return symbol.offset;
-            }
-        }
+        self.get(s).and_then(|symbol| symbol.offset)
+    }
 
-        None
+    /// Given the name of a constant, return its value
+    pub fn constant_value(&self, s: &str) -> Option<i64> {
+        self.get(s).and_then(|symbol| symbol.value)
+    }
+
+    /// Given a data label, return how many bytes of data it names
+    pub fn symbol_size(&self, s: &str) -> Option<u32> {
+        self.get(s).and_then(|symbol| symbol.size)
+    }
+
+    pub fn set_symbol_size(&mut self, s: &str, size: u32) -> bool {
+        self.update(s, |symbol| symbol.size = Some(size))
+    }
+
+    /// Given a label, return the section it points into
+    pub fn symbol_section(&self, s: &str) -> Option<Section> {
+        self.get(s).and_then(Symbol::section)
     }
 
     pub fn set_symbol_offset(&mut self, s: &str, offset: u32) -> bool {
-        for symbol in &mut self.symbols {
-            if symbol.name == s {
-                symbol.offset = Some(offset);
-                return true;
+        self.update(s, |symbol| symbol.offset = Some(offset))
+    }
+
+    pub fn set_symbol_visibility(&mut self, s: &str, visibility: Visibility) -> bool {
+        self.update(s, |symbol| symbol.visibility = visibility)
+    }
+
+    /// Changes the symbol named `s`, returning false if there is none
+    fn update<F: FnOnce(&mut Symbol)>(&mut self, s: &str, f: F) -> bool {
+        match self.get_mut(s) {
+            Some(symbol) => {
+                f(symbol);
+                true
             }
+            None => false,
         }
-
-        false
     }
 }
 
 /// Two pass for Assembler
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub enum AssemblerPhase {
+    #[default]
     First,
     Second,
 }
 
-impl Default for AssemblerPhase {
-    fn default() -> Self {
-        AssemblerPhase::First
-    }
-}
-
 #[cfg(test)]
 mod tests {
-    #![allow(unused_imports)]
+    #![allow(unused_imports, clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -98,11 +250,11 @@ mod tests {
         let mut sym = SymbolTable::new();
         let new_symbol = Symbol::new_with_offset(
             "test".to_string(),
-            SymbolType::Label,
+            SymbolType::CodeLabel,
             12,
         );
         sym.add_symbol(new_symbol);
-        assert_eq!(sym.symbols.len(), 1);
+        assert_eq!(sym.len(), 1);
         let v = sym.symbol_value("test");
         assert_eq!(true, v.is_some());
         let v = v.unwrap();
@@ -110,4 +262,38 @@ mod tests {
         let v = sym.symbol_value("does_not_exist");
         assert_eq!(v.is_some(), false);
     }
+
+    #[test]
+    fn test_symbol_kinds() {
+        let mut sym = SymbolTable::new();
+        assert_eq!(sym.add_symbol(Symbol::new("msg".to_string(), SymbolType::DataLabel)), true);
+        assert_eq!(sym.add_symbol(Symbol::new_constant("SIZE".to_string(), 4)), true);
+        assert_eq!(sym.add_symbol(Symbol::new("print".to_string(), SymbolType::External)), true);
+        assert_eq!(sym.add_symbol(Symbol::new_constant("msg".to_string(), 1)), false);
+        assert_eq!(sym.is_empty(), false);
+
+        assert_eq!(sym.set_symbol_size("msg", 5), true);
+        assert_eq!(sym.set_symbol_visibility("msg", Visibility::Global), true);
+        assert_eq!(sym.set_symbol_offset("missing", 0), false);
+        let msg = sym.get("msg").unwrap();
+        assert_eq!((msg.size(), msg.section(), msg.visibility()), (Some(5), Some(Section::ReadOnly), Visibility::Global));
+        assert_eq!(sym.symbol_section("SIZE"), None);
+        assert_eq!(sym.get("print").unwrap().visibility(), Visibility::Global);
+
+        let names: Vec<&str> = sym.iter().map(Symbol::name).collect();
+        assert_eq!(names, vec!["msg", "SIZE", "print"]);
+    }
+
+    #[test]
+    fn test_many_symbols() {
+        let mut sym = SymbolTable::new();
+        for i in 0..10_000 {
+            sym.add_symbol(Symbol::new_with_offset(format!("label{}", i), SymbolType::CodeLabel, i));
+        }
+        assert_eq!(sym.len(), 10_000);
+        assert_eq!(sym.symbol_value("label9999"), Some(9999));
+        sym.add_float_constant(2.5, 8);
+        assert_eq!(sym.float_constant_offset(2.5), Some(8));
+        assert_eq!(sym.float_constant_offset(-2.5), None);
+    }
 }
@@ -8,15 +8,15 @@ extern crate my_iridium;
 extern crate num_cpus;
 extern crate uuid;
 
-use clap::App;
+use clap::{App, ArgMatches};
 use my_iridium::assembler;
+use my_iridium::disassembler;
+use my_iridium::linker;
+use my_iridium::linker::object::{ObjectFile, OBJECT_HEADER_PREFIX};
 use my_iridium::repl::REPL;
 use my_iridium::vm;
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 
 fn main() {
@@ -25,6 +25,21 @@ fn main() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
 
+    if let Some(sub_matches) = matches.subcommand_matches("disasm") {
+        disassemble_file(sub_matches.value_of("INPUT_FILE").unwrap(), new_assembler(&matches));
+        process::exit(0);
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("assemble") {
+        assemble_file(sub_matches, new_assembler(&matches));
+        process::exit(0);
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("link") {
+        link_files(sub_matches, &matches);
+        process::exit(0);
+    }
+
     let data_root_dir = matches
         .value_of("DATA_ROOT_DIR")
         .unwrap_or("/var/lib/iridium");
@@ -57,15 +72,20 @@ fn main() {
 
     match matches.value_of("INPUT_FILE") {
         Some(filename) => {
-            let program = read_file(filename);
-            let mut asm = assembler::Assembler::new();
             let mut vm = vm::VM::new();
             vm.logical_cores = num_threads;
-            let program = asm.assemble(&program);
+            // Linked programs are run as they are, anything else is assembled first
+            let program = match std::fs::read(filename) {
+                Ok(ref bytes) if bytes.starts_with(&assembler::PIE_HEADER_PREFIX) => Ok(bytes.clone()),
+                _ => new_assembler(&matches).assemble_file(filename),
+            };
 
             match program {
                 Ok(p) => {
-                    vm.add_bytes(p);
+                    if let Err(e) = vm.load_program(p) {
+                        println!("Unable to load program: {}", e);
+                        process::exit(1);
+                    }
                     let events = vm.run();
                     println!("VM Events");
                     println!("--------------");
@@ -75,14 +95,17 @@ fn main() {
                     process::exit(0);
                 },
 
-                Err(e) => {
-                    println!("program running error {:?}", e);
+                Err(errors) => {
+                    for error in errors {
+                        println!("{}", error.render());
+                    }
+                    process::exit(1);
                 },
             }
         },
         None => {
             let mut repl = REPL::new();
-            let mut rx = repl.rx_pipe.take();
+            let rx = repl.rx_pipe.take();
             thread::spawn(move || {
                 let chan = rx.unwrap();
                 loop {
@@ -105,28 +128,128 @@ fn start_remote_server(host: String, port: String) {
     });
 }
 
-fn start_repl() {
-    let mut repl = REPL::new();
-    repl.run();
+/// An assembler that also looks for included files in the `--include-dir` directories
+fn new_assembler(matches: &ArgMatches) -> assembler::Assembler {
+    let mut asm = assembler::Assembler::new();
+    if let Some(dirs) = matches.values_of("INCLUDE_DIR") {
+        for dir in dirs {
+            asm.add_include_dir(dir);
+        }
+    }
+    asm
 }
 
-fn read_file(path: &str) -> String {
-    match File::open(Path::new(&path)) {
-        Ok(mut fh) => {
-            let mut contents = String::new();
-            match fh.read_to_string(&mut contents) {
-                Ok(_) => {
-                    contents
-                },
-                Err(e) => {
-                    println!("There was an error reading file: {:?}", e);
-                    std::process::exit(1);
+/// Writes the program, or the object with `--object`, that an .iasm file assembles to
+fn assemble_file(matches: &ArgMatches, mut asm: assembler::Assembler) {
+    let input = matches.value_of("INPUT_FILE").unwrap();
+    let object = matches.is_present("OBJECT");
+    if matches.is_present("LISTING_FILE") {
+        asm.enable_listing();
+    }
+    let result = if object {
+        asm.assemble_object_file(input).map(|object| object.to_bytes())
+    } else {
+        asm.assemble_file(input)
+    };
+    let bytes = match result {
+        Ok(bytes) => bytes,
+        Err(errors) => {
+            for error in errors {
+                println!("{}", error.render());
+            }
+            process::exit(1);
+        }
+    };
+
+    let output = match matches.value_of("OUTPUT_FILE") {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(input).with_extension(if object { "o" } else { "pie" }),
+    };
+    write_file(&output, &bytes);
+
+    if let (Some(path), Some(listing)) = (matches.value_of("LISTING_FILE"), asm.listing()) {
+        write_file(Path::new(path), listing.as_bytes());
+    }
+    if let Some(path) = matches.value_of("MAP_FILE") {
+        write_file(Path::new(path), asm.symbol_map().as_bytes());
+    }
+}
+
+/// Links object files into a program. Sources are assembled into objects on the way
+fn link_files(matches: &ArgMatches, global_matches: &ArgMatches) {
+    let mut objects = vec![];
+    for path in matches.values_of("INPUT_FILES").unwrap() {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                println!("File not found: {:?}", e);
+                process::exit(1);
+            }
+        };
+
+        let object = if bytes.starts_with(&OBJECT_HEADER_PREFIX) {
+            ObjectFile::parse(&bytes).unwrap_or_else(|e| {
+                println!("Unable to read {}: {}", path, e);
+                process::exit(1);
+            })
+        } else {
+            new_assembler(global_matches).assemble_object_file(path).unwrap_or_else(|errors| {
+                for error in errors {
+                    println!("{}", error.render());
                 }
+                process::exit(1);
+            })
+        };
+        objects.push(object);
+    }
+
+    match linker::link(&objects) {
+        Ok(program) => write_file(Path::new(matches.value_of("OUTPUT_FILE").unwrap_or("a.pie")), &program),
+        Err(errors) => {
+            for error in errors {
+                println!("error: {}", error);
             }
-        },
+            process::exit(1);
+        }
+    }
+}
+
+fn write_file(path: &Path, bytes: &[u8]) {
+    if let Err(e) = std::fs::write(path, bytes) {
+        println!("Unable to write {}: {:?}", path.display(), e);
+        process::exit(1);
+    }
+}
+
+fn disassemble_file(path: &str, mut asm: assembler::Assembler) {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
         Err(e) => {
             println!("File not found: {:?}", e);
-            std::process::exit(1)
+            process::exit(1);
+        }
+    };
+
+    // Anything that is not already a PIE program is treated as assembly source
+    let program = if bytes.starts_with(&assembler::PIE_HEADER_PREFIX) {
+        bytes
+    } else {
+        match asm.assemble_file(path) {
+            Ok(program) => program,
+            Err(errors) => {
+                for error in errors {
+                    println!("{}", error.render());
+                }
+                process::exit(1);
+            }
+        }
+    };
+
+    match disassembler::disassemble(&program) {
+        Ok(text) => print!("{}", text),
+        Err(e) => {
+            println!("Unable to disassemble program: {}", e);
+            process::exit(1);
         }
     }
 }
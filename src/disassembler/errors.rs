@@ -0,0 +1,47 @@
+use std::error::Error;
+use std::fmt;
+use vm::errors::VmError;
+
+/// Errors raised while turning bytecode back into assembly
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisassemblerError {
+    InvalidImage { error: VmError },
+    TruncatedInstruction { offset: usize },
+    IllegalOpcode { offset: usize, opcode: u8 },
+    UnrepresentableData { offset: usize },
+    NotReproducible,
+}
+
+impl fmt::Display for DisassemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DisassemblerError::InvalidImage { ref error } => {
+                f.write_str(&format!("The bytes are not a valid program: {}", error))
+            }
+            DisassemblerError::TruncatedInstruction { offset } => {
+                f.write_str(&format!("The instruction at byte {} is cut short", offset))
+            }
+            DisassemblerError::IllegalOpcode { offset, opcode } => {
+                f.write_str(&format!("Illegal opcode {} at byte {}", opcode, offset))
+            }
+            DisassemblerError::UnrepresentableData { offset } => {
+                f.write_str(&format!("No float constant fits at read-only offset {}", offset))
+            }
+            DisassemblerError::NotReproducible => {
+                f.write_str("The program cannot be written as assembly that assembles to the same bytes")
+            }
+        }
+    }
+}
+
+impl Error for DisassemblerError {
+    fn description(&self) -> &str {
+        match self {
+            DisassemblerError::InvalidImage { .. } => "The bytes are not a valid program",
+            DisassemblerError::TruncatedInstruction { .. } => "An instruction is cut short",
+            DisassemblerError::IllegalOpcode { .. } => "Illegal opcode encountered",
+            DisassemblerError::UnrepresentableData { .. } => "Read-only data cannot be written as assembly",
+            DisassemblerError::NotReproducible => "The program cannot be reassembled to the same bytes",
+        }
+    }
+}
@@ -0,0 +1,367 @@
+pub mod errors;
+
+use assembler::{Assembler, INSTRUCTION_LENGTH};
+use byteorder::{LittleEndian, ReadBytesExt};
+use disassembler::errors::DisassemblerError;
+use instruction::{Opcode, OperandKind};
+use vm::image::ProgramImage;
+
+/// An instruction decoded from the code section
+#[derive(Debug, PartialEq)]
+struct DecodedInstruction {
+    /// Absolute address of the instruction in the program
+    address: usize,
+    opcode: Opcode,
+    /// Raw operand values, in the order given by `Opcode::operands`
+    operands: Vec<u32>,
+}
+
+/// A piece of the read-only section, written back as the directive that stores it
+#[derive(Debug, PartialEq)]
+enum RoData {
+    /// A null-terminated string, written as `.asciiz`
+    Text(String),
+    /// Anything else, written as `.byte`
+    Bytes(Vec<u8>),
+}
+
+/// How many values go on each `.byte` line
+const BYTES_PER_LINE: usize = 16;
+
+/// Turns a PIE program back into assembly text.
+/// The text is checked to assemble to exactly the same bytes before it is returned
+pub fn disassemble(bytes: &[u8]) -> Result<String, DisassemblerError> {
+    let image = ProgramImage::parse(bytes).map_err(|error| DisassemblerError::InvalidImage { error })?;
+    let instructions = decode_code(&image)?;
+
+    let pool_start = float_pool_start(&instructions, &image.ro_data)?;
+    // Data read by an instruction gets a label of its own
+    let targets: Vec<usize> = instructions.iter().flat_map(ro_offsets).collect();
+    let data = decode_ro(&image.ro_data[..pool_start], &targets);
+
+    let mut text = String::from(".data\n");
+    for (offset, item) in &data {
+        match item {
+            RoData::Text(string) => text.push_str(&format!("ro{}: .asciiz '{}'\n", offset, string)),
+            RoData::Bytes(bytes) => {
+                for (i, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+                    if i == 0 {
+                        text.push_str(&format!("ro{}: ", offset));
+                    }
+                    text.push_str(".byte");
+                    for byte in line {
+                        text.push_str(&format!(" #{}", byte));
+                    }
+                    text.push('\n');
+                }
+            }
+        }
+    }
+    text.push_str(".code\n");
+    render_code(&mut text, &instructions, &data, &image.ro_data, pool_start);
+
+    match Assembler::new().assemble(&text) {
+        Ok(ref reassembled) if reassembled.as_slice() == bytes => Ok(text),
+        _ => Err(DisassemblerError::NotReproducible),
+    }
+}
+
+/// Splits the code section into instructions
+fn decode_code(image: &ProgramImage) -> Result<Vec<DecodedInstruction>, DisassemblerError> {
+    let code_offset = image.code_offset();
+    let mut instructions = vec![];
+    for (i, chunk) in image.code.chunks(INSTRUCTION_LENGTH as usize).enumerate() {
+        let address = code_offset + i * INSTRUCTION_LENGTH as usize;
+        if chunk.len() < INSTRUCTION_LENGTH as usize {
+            return Err(DisassemblerError::TruncatedInstruction { offset: address });
+        }
+
+        let opcode = Opcode::from(chunk[0]);
+        if u8::from(opcode) != chunk[0] {
+            return Err(DisassemblerError::IllegalOpcode { offset: address, opcode: chunk[0] });
+        }
+
+        let mut operands = vec![];
+        let mut position = 1;
+        for kind in opcode.operands() {
+            let value = match kind.width() {
+                1 => u32::from(chunk[position]),
+                _ => (u32::from(chunk[position]) << 8) | u32::from(chunk[position + 1]),
+            };
+            operands.push(value);
+            position += kind.width();
+        }
+        instructions.push(DecodedInstruction { address, opcode, operands });
+    }
+    Ok(instructions)
+}
+
+/// Where the float constants the assembler pools for `loadf64` start in the read-only section.
+/// They come last, 8 bytes each, in the order `loadcf64` first reads them, and the values are distinct.
+/// Anything else `loadcf64` reads, such as a `.float`, is data of the program
+fn float_pool_start(instructions: &[DecodedInstruction], ro: &[u8]) -> Result<usize, DisassemblerError> {
+    let mut reads: Vec<usize> = vec![];
+    for inst in instructions {
+        if inst.opcode != Opcode::LOADCF64 {
+            continue;
+        }
+        let offset = inst.operands[1] as usize;
+        if offset + 8 > ro.len() {
+            return Err(DisassemblerError::UnrepresentableData { offset });
+        }
+        if !reads.contains(&offset) {
+            reads.push(offset);
+        }
+    }
+    // Other instructions can't read the pool
+    let others = instructions.iter().filter(|inst| inst.opcode != Opcode::LOADCF64).flat_map(ro_offsets).max();
+
+    // The largest run of slots at the end that reads like a pool
+    let mut count = ro.len() / 8;
+    loop {
+        let start = ro.len() - count * 8;
+        let in_pool: Vec<usize> = reads.iter().cloned().filter(|&offset| offset >= start).collect();
+        let slots: Vec<usize> = (0..count).map(|i| start + i * 8).collect();
+        let values: Vec<&[u8]> = slots.iter().map(|&slot| &ro[slot..slot + 8]).collect();
+        let distinct = values.iter().enumerate().all(|(i, value)| !values[..i].contains(value));
+        if count == 0 || (in_pool == slots && distinct && others.is_none_or(|offset| offset < start)) {
+            return Ok(start);
+        }
+        count -= 1;
+    }
+}
+
+/// The read-only offsets an instruction reads
+fn ro_offsets(inst: &DecodedInstruction) -> Vec<usize> {
+    inst.opcode
+        .operands()
+        .iter()
+        .zip(inst.operands.iter())
+        .filter(|(kind, _)| **kind == OperandKind::RoOffset)
+        .map(|(_, value)| *value as usize)
+        .collect()
+}
+
+/// Splits the read-only section into strings and runs of other bytes.
+/// A new piece starts at every offset in `targets`, so it can be labelled
+fn decode_ro(ro: &[u8], targets: &[usize]) -> Vec<(usize, RoData)> {
+    let mut data = vec![];
+    let mut offset = 0;
+    while offset < ro.len() {
+        // Whatever is there, it stops at the next target
+        let limit = targets
+            .iter()
+            .filter(|&&t| t > offset)
+            .min()
+            .map_or(ro.len(), |&t| t.min(ro.len()));
+        let terminator = ro[offset..limit].iter().position(|&b| b == 0).map(|len| offset + len);
+
+        let text = terminator
+            .and_then(|end| ::std::str::from_utf8(&ro[offset..end]).ok())
+            // Only printable text reads back the same from `.asciiz`, tables of small numbers stay bytes
+            .filter(|s| !s.is_empty() && !s.contains('\'') && !s.chars().any(char::is_control));
+        let end = match (text, terminator) {
+            (Some(s), Some(end)) => {
+                data.push((offset, RoData::Text(s.to_string())));
+                offset = end + 1;
+                continue;
+            }
+            (_, Some(end)) => end + 1,
+            (_, None) => limit,
+        };
+
+        // Padding and tables come out as many short runs, keep them on as few lines as possible
+        match data.last_mut() {
+            Some((start, RoData::Bytes(bytes))) if *start + bytes.len() == offset && !targets.contains(&offset) => {
+                bytes.extend_from_slice(&ro[offset..end])
+            }
+            _ => data.push((offset, RoData::Bytes(ro[offset..end].to_vec()))),
+        }
+        offset = end;
+    }
+    data
+}
+
+/// Writes one line per instruction, labelled with its address
+fn render_code(
+    text: &mut String,
+    instructions: &[DecodedInstruction],
+    data: &[(usize, RoData)],
+    ro: &[u8],
+    pool_start: usize,
+) {
+    let targets: Vec<u32> = instructions
+        .iter()
+        .flat_map(|inst| {
+            inst.opcode
+                .operands()
+                .iter()
+                .zip(inst.operands.iter())
+                .filter(|(kind, _)| **kind == OperandKind::Address)
+                .map(|(_, value)| *value)
+        })
+        .collect();
+
+    let mut i = 0;
+    while i < instructions.len() {
+        let inst = &instructions[i];
+        text.push_str(&format!("addr{}: ", inst.address));
+
+        // A `load` of a 32 bits value is written back as the single wide `load` it came from
+        if let Some(value) = wide_load(inst, instructions.get(i + 1), &targets) {
+            text.push_str(&format!("load ${} #{}\n", inst.operands[0], value));
+            i += 2;
+            continue;
+        }
+
+        // A pooled constant goes back to the `loadf64` immediate it came from
+        if inst.opcode == Opcode::LOADCF64 && inst.operands[1] as usize >= pool_start {
+            let offset = inst.operands[1] as usize;
+            let value = (&ro[offset..offset + 8]).read_f64::<LittleEndian>().unwrap();
+            text.push_str(&format!("loadf64 ${} #{:?}\n", inst.operands[0], value));
+            i += 1;
+            continue;
+        }
+
+        text.push_str(inst.opcode.mnemonic());
+        for (kind, value) in inst.opcode.operands().iter().zip(inst.operands.iter()) {
+            let operand = match *kind {
+                OperandKind::IntRegister | OperandKind::FloatRegister => format!("${}", value),
+                OperandKind::Address if instructions.iter().any(|inst| inst.address == *value as usize) => {
+                    format!("@addr{}", value)
+                }
+                OperandKind::RoOffset if data.iter().any(|(offset, _)| *offset == *value as usize) => {
+                    format!("@ro{}", value)
+                }
+                _ => format!("#{}", value),
+            };
+            text.push(' ');
+            text.push_str(&operand);
+        }
+        text.push('\n');
+        i += 1;
+    }
+}
+
+/// A `load` followed by a `loadhi` of the same register holds a single 32 bits value,
+/// unless something jumps in between the two
+fn wide_load(inst: &DecodedInstruction, next: Option<&DecodedInstruction>, targets: &[u32]) -> Option<i32> {
+    let next = next?;
+    if inst.opcode != Opcode::LOAD
+        || next.opcode != Opcode::LOADHI
+        || inst.operands[0] != next.operands[0]
+        || targets.contains(&(next.address as u32))
+    {
+        return None;
+    }
+
+    let value = ((next.operands[1] << 16) | inst.operands[1]) as i32;
+    if value < 0 || value > i32::from(u16::MAX) {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::bool_assert_comparison)]
+
+    use super::*;
+    use vm::errors::VmError;
+
+    fn assemble(source: &str) -> Vec<u8> {
+        Assembler::new().assemble(source).unwrap()
+    }
+
+    #[test]
+    fn test_disassemble_round_trip() {
+        let program = assemble(
+            ".data\nhello: .asciiz 'Hello world'\nbye: .asciiz 'Bye'\n.code\n\
+             load $0 #-70000\nload $1 #10\nloop: dec $1\nloadf64 $2 #2.75\nloadf64 $3 #1\n\
+             loadw $4 $0 #8\nftoi $2 $5 #1\neq $1 $6\njmpe @loop\nprts @bye\ncall @done\n\
+             done: ret\nhlt\n",
+        );
+        let text = disassemble(&program).unwrap();
+        assert_eq!(text.contains("ro0: .asciiz 'Hello world'"), true);
+        assert_eq!(text.contains("addr88: load $0 #-70000"), true);
+        assert_eq!(text.contains("loadf64 $2 #2.75"), true);
+        assert_eq!(text.contains("djmpe @addr100"), true);
+        assert_eq!(text.contains("prts @ro12"), true);
+        assert_eq!(assemble(&text), program);
+    }
+
+    #[test]
+    fn test_disassemble_empty_ro() {
+        let program = assemble(".data\n.code\nload $0 #1\nhlt\n");
+        let text = disassemble(&program).unwrap();
+        assert_eq!(text, ".data\n.code\naddr64: load $0 #1\naddr68: hlt\n");
+    }
+
+    #[test]
+    fn test_disassemble_data() {
+        let program = assemble(
+            ".data\nbytes: .byte #72 #105\ntable: .word #-1 #7\n.align #8\ntext: .asciiz 'ok'\n\
+             .code\nloadf64 $0 #0.5\nprts @text\nhlt\n",
+        );
+        let text = disassemble(&program).unwrap();
+        assert_eq!(text.contains("ro0: .byte #72 #105 #255 #255 #255 #255 #7 #0 #0 #0 #0 #0 #0 #0 #0 #0\n"), true);
+        assert_eq!(text.contains("ro16: .asciiz 'ok'"), true);
+        assert_eq!(text.contains("prts @ro16"), true);
+        assert_eq!(assemble(&text), program);
+    }
+
+    #[test]
+    fn test_disassemble_float_data() {
+        let program = assemble(
+            ".data\nf: .float #1.5\ns: .asciiz 'hi'\n.code\nloadcf64 $0 @f\nloadf64 $1 #2.5\nprts @s\n\
+             loadf64 $2 #1.5\nhlt\n",
+        );
+        let text = disassemble(&program).unwrap();
+        assert!(text.contains("ro0: .byte #0 #0 #0 #0 #0 #0 #248 #63\n"));
+        assert!(text.contains("ro8: .asciiz 'hi'"));
+        assert!(text.contains("loadcf64 $0 @ro0\n"));
+        assert!(text.contains("loadf64 $1 #2.5\n"));
+        assert!(text.contains("prts @ro8\n"));
+        assert!(text.contains("loadf64 $2 #1.5\n"));
+        assert_eq!(assemble(&text), program);
+    }
+
+    #[test]
+    fn test_disassemble_control_bytes() {
+        let program = assemble(".data\ntable: .byte #1 #2 #3 #0\ntab: .byte #9 #0\n.code\nhlt\n");
+        let text = disassemble(&program).unwrap();
+        assert_eq!(text, ".data\nro0: .byte #1 #2 #3 #0 #9 #0\n.code\naddr70: hlt\n");
+        assert_eq!(assemble(&text), program);
+    }
+
+    #[test]
+    fn test_disassemble_invalid_program() {
+        assert_eq!(
+            disassemble(&[0x45, 0x50]),
+            Err(DisassemblerError::InvalidImage { error: VmError::InvalidHeader })
+        );
+
+        let image = ProgramImage::new(vec![], vec![200, 0, 0, 0]);
+        assert_eq!(
+            disassemble(&image.to_bytes()),
+            Err(DisassemblerError::IllegalOpcode { offset: 64, opcode: 200 })
+        );
+
+        let image = ProgramImage::new(vec![], vec![5, 0, 0, 0, 5, 0]);
+        assert_eq!(
+            disassemble(&image.to_bytes()),
+            Err(DisassemblerError::TruncatedInstruction { offset: 68 })
+        );
+
+        let image = ProgramImage::new(vec![], vec![u8::from(Opcode::LOADCF64), 0, 0, 0]);
+        assert_eq!(
+            disassemble(&image.to_bytes()),
+            Err(DisassemblerError::UnrepresentableData { offset: 0 })
+        );
+
+        // Padding bytes after the operands are not kept by the assembler
+        let image = ProgramImage::new(vec![], vec![5, 0, 0, 7]);
+        assert_eq!(disassemble(&image.to_bytes()), Err(DisassemblerError::NotReproducible));
+    }
+}
@@ -1,4 +1,5 @@
 use nom::types::CompleteStr;
+use std::fmt;
 
 /// VM's Opcode
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -48,7 +49,10 @@ pub enum Opcode {
     /// Decrease one
     DEC,
 
+    /// If equal_flag == true then jmp to a 16 bits address
     DJMPE,
+    /// Absolute Jumps to a 16 bits address
+    DJMP,
     PRTS,
     /// Illegal opcode
     IGL,
@@ -65,6 +69,76 @@ pub enum Opcode {
     GTEF64,
     LTF64,
     LTEF64,
+
+    /// Push the return address and jump to a 16 bits address
+    CALL,
+    /// Pop the return address and jump back to it
+    RET,
+    /// Push an integer register onto the stack
+    PUSH,
+    /// Pop the top of the stack into an integer register
+    POP,
+    /// Push a float register onto the stack
+    PUSHF64,
+    /// Pop the top of the stack into a float register
+    POPF64,
+
+    /// Load an unsigned byte from heap[base + offset]: dst base offset
+    LOADB,
+    /// Load an unsigned 16 bits half word from the heap: dst base offset
+    LOADH,
+    /// Load a 32 bits word from the heap: dst base offset
+    LOADW,
+    /// Load a 64 bits double from the heap into a float register: dst base offset
+    LOADD,
+    /// Store the low byte of a register to heap[base + offset]: src base offset
+    STOREB,
+    /// Store the low 16 bits of a register to the heap: src base offset
+    STOREH,
+    /// Store a 32 bits word to the heap: src base offset
+    STOREW,
+    /// Store a float register as a 64 bits double to the heap: src base offset
+    STORED,
+    /// Shrink the heap by the number of bytes in a register, undoing `ALOC`
+    FREE,
+
+    /// Bitwise and: src1 src2 dst
+    AND,
+    /// Bitwise or: src1 src2 dst
+    OR,
+    /// Bitwise xor: src1 src2 dst
+    XOR,
+    /// Bitwise not: src dst
+    NOT,
+    /// Shift left by the low 5 bits of src2: src1 src2 dst
+    SHL,
+    /// Logical shift right, filling with zeros: src1 src2 dst
+    SHR,
+    /// Arithmetic shift right, keeping the sign: src1 src2 dst
+    SAR,
+    /// Remainder of src1 / src2: src1 src2 dst
+    MOD,
+
+    /// Replace the upper 16 bits of a register, keeping the lower 16 bits from a previous LOAD
+    LOADHI,
+    /// Load a 64 bits double stored in the read-only section: dst offset
+    LOADCF64,
+
+    /// Convert an integer register to a float register: src dst
+    ITOF,
+    /// Convert a float register to an integer register: src dst [mode],
+    /// where mode is 0 = truncate, 1 = round, 2 = floor, 3 = ceil
+    FTOI,
+    /// Copy an integer register: src dst
+    MOV,
+    /// Copy a float register: src dst
+    MOVF64,
+    /// Negate a float register: src dst
+    NEGF64,
+    /// Absolute value of a float register: src dst
+    ABSF64,
+    /// Square root of a float register: src dst
+    SQRTF64,
 }
 
 
@@ -114,6 +188,45 @@ impl From<Opcode> for u8 {
             LTF64 => 31,
             LTEF64 => 32,
 
+            DJMP => 33,
+
+            CALL => 34,
+            RET => 35,
+            PUSH => 36,
+            POP => 37,
+            PUSHF64 => 38,
+            POPF64 => 39,
+
+            LOADB => 40,
+            LOADH => 41,
+            LOADW => 42,
+            LOADD => 43,
+            STOREB => 44,
+            STOREH => 45,
+            STOREW => 46,
+            STORED => 47,
+            FREE => 48,
+
+            AND => 49,
+            OR => 50,
+            XOR => 51,
+            NOT => 52,
+            SHL => 53,
+            SHR => 54,
+            SAR => 55,
+            MOD => 56,
+
+            LOADHI => 57,
+            LOADCF64 => 58,
+
+            ITOF => 59,
+            FTOI => 60,
+            MOV => 61,
+            MOVF64 => 62,
+            NEGF64 => 63,
+            ABSF64 => 64,
+            SQRTF64 => 65,
+
             IGL => 100,
         }
     }
@@ -165,48 +278,283 @@ impl From<u8> for Opcode {
             31 => LTF64,
             32 => LTEF64,
 
+            33 => DJMP,
+
+            34 => CALL,
+            35 => RET,
+            36 => PUSH,
+            37 => POP,
+            38 => PUSHF64,
+            39 => POPF64,
+
+            40 => LOADB,
+            41 => LOADH,
+            42 => LOADW,
+            43 => LOADD,
+            44 => STOREB,
+            45 => STOREH,
+            46 => STOREW,
+            47 => STORED,
+            48 => FREE,
+
+            49 => AND,
+            50 => OR,
+            51 => XOR,
+            52 => NOT,
+            53 => SHL,
+            54 => SHR,
+            55 => SAR,
+            56 => MOD,
+
+            57 => LOADHI,
+            58 => LOADCF64,
+
+            59 => ITOF,
+            60 => FTOI,
+            61 => MOV,
+            62 => MOVF64,
+            63 => NEGF64,
+            64 => ABSF64,
+            65 => SQRTF64,
+
             _ => IGL,
         }
     }
 }
 
+/// Every assembler mnemonic together with the opcode it encodes to
+pub const MNEMONICS: &[(&str, Opcode)] = &[
+    ("load", Opcode::LOAD),
+    ("add", Opcode::ADD),
+    ("sub", Opcode::SUB),
+    ("mul", Opcode::MUL),
+    ("div", Opcode::DIV),
+    ("hlt", Opcode::HLT),
+    ("jmp", Opcode::JMP),
+    ("jmpf", Opcode::JMPF),
+    ("jmpb", Opcode::JMPB),
+    ("eq", Opcode::EQ),
+    ("neq", Opcode::NEQ),
+    ("gte", Opcode::GTE),
+    ("gt", Opcode::GT),
+    ("lte", Opcode::LTE),
+    ("lt", Opcode::LT),
+    ("jmpe", Opcode::JMPE),
+    ("nop", Opcode::NOP),
+    ("aloc", Opcode::ALOC),
+    ("inc", Opcode::INC),
+    ("dec", Opcode::DEC),
+    ("djmpe", Opcode::DJMPE),
+    ("djmp", Opcode::DJMP),
+    ("prts", Opcode::PRTS),
+    ("igl", Opcode::IGL),
+    ("loadf64", Opcode::LOADF64),
+    ("addf64", Opcode::ADDF64),
+    ("subf64", Opcode::SUBF64),
+    ("mulf64", Opcode::MULF64),
+    ("divf64", Opcode::DIVF64),
+    ("eqf64", Opcode::EQF64),
+    ("neqf64", Opcode::NEQF64),
+    ("gtf64", Opcode::GTF64),
+    ("gtef64", Opcode::GTEF64),
+    ("ltf64", Opcode::LTF64),
+    ("ltef64", Opcode::LTEF64),
+    ("call", Opcode::CALL),
+    ("ret", Opcode::RET),
+    ("push", Opcode::PUSH),
+    ("pop", Opcode::POP),
+    ("pushf64", Opcode::PUSHF64),
+    ("popf64", Opcode::POPF64),
+    ("loadb", Opcode::LOADB),
+    ("loadh", Opcode::LOADH),
+    ("loadw", Opcode::LOADW),
+    ("loadd", Opcode::LOADD),
+    ("storeb", Opcode::STOREB),
+    ("storeh", Opcode::STOREH),
+    ("storew", Opcode::STOREW),
+    ("stored", Opcode::STORED),
+    ("free", Opcode::FREE),
+    ("and", Opcode::AND),
+    ("or", Opcode::OR),
+    ("xor", Opcode::XOR),
+    ("not", Opcode::NOT),
+    ("shl", Opcode::SHL),
+    ("shr", Opcode::SHR),
+    ("sar", Opcode::SAR),
+    ("mod", Opcode::MOD),
+    ("loadhi", Opcode::LOADHI),
+    ("loadcf64", Opcode::LOADCF64),
+    ("itof", Opcode::ITOF),
+    ("ftoi", Opcode::FTOI),
+    ("mov", Opcode::MOV),
+    ("movf64", Opcode::MOVF64),
+    ("negf64", Opcode::NEGF64),
+    ("absf64", Opcode::ABSF64),
+    ("sqrtf64", Opcode::SQRTF64),
+];
+
+/// Unknown names become `IGL`. The assembler uses `Opcode::from_mnemonic` instead so it can report them
 impl<'a> From<CompleteStr<'a>> for Opcode {
     fn from(v: CompleteStr<'a>) -> Self {
-        match v {
-            CompleteStr("load") => Opcode::LOAD,
-            CompleteStr("add") => Opcode::ADD,
-            CompleteStr("sub") => Opcode::SUB,
-            CompleteStr("mul") => Opcode::MUL,
-            CompleteStr("div") => Opcode::DIV,
-            CompleteStr("hlt") => Opcode::HLT,
-            CompleteStr("jmp") => Opcode::JMP,
-            CompleteStr("jmpf") => Opcode::JMPF,
-            CompleteStr("jmpb") => Opcode::JMPB,
-            CompleteStr("eq") => Opcode::EQ,
-            CompleteStr("neq") => Opcode::NEQ,
-            CompleteStr("gte") => Opcode::GTE,
-            CompleteStr("gt") => Opcode::GT,
-            CompleteStr("lte") => Opcode::LTE,
-            CompleteStr("lt") => Opcode::LT,
-            CompleteStr("jmpe") => Opcode::JMPE,
-            CompleteStr("nop") => Opcode::NOP,
-            CompleteStr("aloc") => Opcode::ALOC,
-            CompleteStr("inc") => Opcode::INC,
-            CompleteStr("dec") => Opcode::DEC,
-            CompleteStr("djmpe") => Opcode::DJMPE,
-            CompleteStr("prts") => Opcode::PRTS,
-            CompleteStr("loadf64") => Opcode::LOADF64,
-            CompleteStr("addf64") => Opcode::ADDF64,
-            CompleteStr("subf64") => Opcode::SUBF64,
-            CompleteStr("mulf64") => Opcode::MULF64,
-            CompleteStr("divf64") => Opcode::DIVF64,
-            CompleteStr("eqf64") => Opcode::EQF64,
-            CompleteStr("neqf64") => Opcode::NEQF64,
-            CompleteStr("gtf64") => Opcode::GTF64,
-            CompleteStr("gtef64") => Opcode::GTEF64,
-            CompleteStr("ltf64") => Opcode::LTF64,
-            CompleteStr("ltef64") => Opcode::LTEF64,
-            _ => Opcode::IGL,
+        Opcode::from_mnemonic(v.0).unwrap_or(Opcode::IGL)
+    }
+}
+
+/// The known mnemonic closest to `name`, if one is only a typo away
+pub fn closest_mnemonic(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    // Short words are only a typo away from too many mnemonics to allow more than one edit
+    let allowed = (name.chars().count() / 3).max(1);
+    MNEMONICS
+        .iter()
+        .map(|(mnemonic, _)| (*mnemonic, edit_distance(&name, mnemonic)))
+        .filter(|(_, distance)| *distance <= allowed)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(mnemonic, _)| mnemonic)
+}
+
+/// Levenshtein distance: how many characters to insert, delete or replace to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replace = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// What kind of value an operand of an instruction encodes
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum OperandKind {
+    /// One of the 32 integer registers, one byte
+    IntRegister,
+    /// One of the 32 float registers, one byte
+    FloatRegister,
+    /// An 8 bits immediate in the last byte of the instruction
+    Immediate8,
+    /// A 16 bits big endian immediate
+    Immediate16,
+    /// A 16 bits absolute address in the program
+    Address,
+    /// A 16 bits offset into the read-only section
+    RoOffset,
+}
+
+impl OperandKind {
+    /// How many bytes the operand takes in the bytecode
+    pub fn width(self) -> usize {
+        match self {
+            OperandKind::IntRegister | OperandKind::FloatRegister | OperandKind::Immediate8 => 1,
+            OperandKind::Immediate16 | OperandKind::Address | OperandKind::RoOffset => 2,
+        }
+    }
+}
+
+impl fmt::Display for OperandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OperandKind::IntRegister => f.write_str("an integer register"),
+            OperandKind::FloatRegister => f.write_str("a float register"),
+            OperandKind::Immediate8 => f.write_str("an 8 bits integer"),
+            OperandKind::Immediate16 => f.write_str("a 16 bits integer or a label"),
+            OperandKind::Address => f.write_str("a label or an address"),
+            OperandKind::RoOffset => f.write_str("a label or a read-only offset"),
+        }
+    }
+}
+
+impl Opcode {
+    /// The opcode written as `name` in assembly, if there is one
+    pub fn from_mnemonic(name: &str) -> Option<Opcode> {
+        MNEMONICS.iter().find(|(mnemonic, _)| *mnemonic == name).map(|(_, opcode)| *opcode)
+    }
+
+    /// The lowercase name the assembler knows this opcode by
+    pub fn mnemonic(self) -> &'static str {
+        MNEMONICS
+            .iter()
+            .find(|(_, opcode)| *opcode == self)
+            .map(|(name, _)| *name)
+            .unwrap_or("igl")
+    }
+
+    /// How many of the leading operands have to be written out.
+    /// The rest may be left off and are encoded as zero
+    pub fn required_operands(self) -> usize {
+        match self {
+            // The rounding mode defaults to truncation
+            Opcode::FTOI => 2,
+            _ => self.operands().len(),
+        }
+    }
+
+    /// The operands the opcode expects, in the order they are encoded
+    pub fn operands(self) -> &'static [OperandKind] {
+        use self::OperandKind::*;
+        match self {
+            Opcode::LOAD | Opcode::LOADHI => &[IntRegister, Immediate16],
+            Opcode::LOADF64 => &[FloatRegister, Immediate16],
+            Opcode::LOADCF64 => &[FloatRegister, RoOffset],
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::DIV
+            | Opcode::MOD
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::SHL
+            | Opcode::SHR
+            | Opcode::SAR => &[IntRegister, IntRegister, IntRegister],
+            Opcode::ADDF64 | Opcode::SUBF64 | Opcode::MULF64 | Opcode::DIVF64 => {
+                &[FloatRegister, FloatRegister, FloatRegister]
+            }
+            Opcode::EQ
+            | Opcode::NEQ
+            | Opcode::GT
+            | Opcode::LT
+            | Opcode::GTE
+            | Opcode::LTE
+            | Opcode::NOT
+            | Opcode::MOV => &[IntRegister, IntRegister],
+            Opcode::EQF64
+            | Opcode::NEQF64
+            | Opcode::GTF64
+            | Opcode::GTEF64
+            | Opcode::LTF64
+            | Opcode::LTEF64
+            | Opcode::MOVF64
+            | Opcode::NEGF64
+            | Opcode::ABSF64
+            | Opcode::SQRTF64 => &[FloatRegister, FloatRegister],
+            Opcode::JMP
+            | Opcode::JMPF
+            | Opcode::JMPB
+            | Opcode::JMPE
+            | Opcode::ALOC
+            | Opcode::FREE
+            | Opcode::INC
+            | Opcode::DEC
+            | Opcode::PUSH
+            | Opcode::POP => &[IntRegister],
+            Opcode::PUSHF64 | Opcode::POPF64 => &[FloatRegister],
+            Opcode::DJMP | Opcode::DJMPE | Opcode::CALL => &[Address],
+            Opcode::PRTS => &[RoOffset],
+            Opcode::LOADB
+            | Opcode::LOADH
+            | Opcode::LOADW
+            | Opcode::STOREB
+            | Opcode::STOREH
+            | Opcode::STOREW => &[IntRegister, IntRegister, Immediate8],
+            Opcode::LOADD | Opcode::STORED => &[FloatRegister, IntRegister, Immediate8],
+            Opcode::ITOF => &[IntRegister, FloatRegister],
+            Opcode::FTOI => &[FloatRegister, IntRegister, Immediate8],
+            Opcode::HLT | Opcode::NOP | Opcode::RET | Opcode::IGL => &[],
         }
     }
 }
@@ -226,7 +574,7 @@ impl Instruction {
 
 #[cfg(test)]
 mod tests {
-    #![allow(unused_imports)]
+    #![allow(unused_imports, clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -249,4 +597,46 @@ mod tests {
         let opcode = Opcode::from(CompleteStr("illegal"));
         assert_eq!(opcode, Opcode::IGL);
     }
+
+    #[test]
+    fn str_to_bitwise_opcode() {
+        let names = ["and", "or", "xor", "not", "shl", "shr", "sar", "mod"];
+        let opcodes = [
+            Opcode::AND,
+            Opcode::OR,
+            Opcode::XOR,
+            Opcode::NOT,
+            Opcode::SHL,
+            Opcode::SHR,
+            Opcode::SAR,
+            Opcode::MOD,
+        ];
+        for (name, opcode) in names.iter().zip(opcodes.iter()) {
+            assert_eq!(Opcode::from(CompleteStr(name)), *opcode);
+            assert_eq!(Opcode::from(u8::from(*opcode)), *opcode);
+        }
+    }
+
+    #[test]
+    fn test_closest_mnemonic() {
+        assert_eq!(closest_mnemonic("lood"), Some("load"));
+        assert_eq!(closest_mnemonic("LOAD"), Some("load"));
+        assert_eq!(closest_mnemonic("loadf46"), Some("loadf64"));
+        assert_eq!(closest_mnemonic("jmpx"), Some("jmp"));
+        assert_eq!(closest_mnemonic("xy"), None);
+        assert_eq!(closest_mnemonic("frobnicate"), None);
+        assert_eq!(Opcode::from_mnemonic("lood"), None);
+        assert_eq!(Opcode::from_mnemonic("igl"), Some(Opcode::IGL));
+    }
+
+    #[test]
+    fn mnemonics_round_trip() {
+        for (name, opcode) in MNEMONICS {
+            assert_eq!(opcode.mnemonic(), *name);
+            assert_eq!(Opcode::from(CompleteStr(name)), *opcode);
+            assert_eq!(Opcode::from(u8::from(*opcode)), *opcode);
+            let width: usize = opcode.operands().iter().map(|kind| kind.width()).sum();
+            assert_eq!(width <= 3, true);
+        }
+    }
 }
\ No newline at end of file
@@ -7,6 +7,8 @@ extern crate uuid;
 
 
 pub mod assembler;
+pub mod disassembler;
+pub mod linker;
 pub mod instruction;
 pub mod repl;
 pub mod vm;
@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::fmt;
+
+/// Errors raised while linking objects into a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkerError {
+    NoObjects,
+    InvalidObject { reason: &'static str },
+    DuplicateSymbol { name: String, first: String, second: String },
+    UndefinedSymbol { name: String, object: String },
+    RelocationOutOfRange { value: i64, bits: u8, object: String },
+    RelocationOutOfBounds { offset: u32, object: String },
+}
+
+impl fmt::Display for LinkerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinkerError::NoObjects => f.write_str("There are no objects to link"),
+            LinkerError::InvalidObject { reason } => {
+                f.write_str(&format!("The bytes are not a valid object file: {}", reason))
+            }
+            LinkerError::DuplicateSymbol { ref name, ref first, ref second } => {
+                f.write_str(&format!("Symbol {} is exported by both {} and {}", name, first, second))
+            }
+            LinkerError::UndefinedSymbol { ref name, ref object } => {
+                f.write_str(&format!("Symbol {} used by {} is not exported by any object", name, object))
+            }
+            LinkerError::RelocationOutOfRange { value, bits, ref object } => {
+                f.write_str(&format!("Address {} used by {} does not fit in {} bits", value, object, bits))
+            }
+            LinkerError::RelocationOutOfBounds { offset, ref object } => {
+                f.write_str(&format!("Relocation at offset {} is outside of its section in {}", offset, object))
+            }
+        }
+    }
+}
+
+impl Error for LinkerError {
+    fn description(&self) -> &str {
+        match self {
+            LinkerError::NoObjects => "There are no objects to link",
+            LinkerError::InvalidObject { .. } => "The bytes are not a valid object file",
+            LinkerError::DuplicateSymbol { .. } => "A symbol is exported by two objects",
+            LinkerError::UndefinedSymbol { .. } => "A symbol is not exported by any object",
+            LinkerError::RelocationOutOfRange { .. } => "An address does not fit in its field",
+            LinkerError::RelocationOutOfBounds { .. } => "A relocation is outside of its section",
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use assembler::PIE_HEADER_LENGTH;
+use byteorder::{LittleEndian, WriteBytesExt};
+use linker::errors::LinkerError;
+use linker::object::{Field, ObjectFile, Relocation, RelocationTarget, Section};
+use std::collections::HashMap;
+use vm::image::ProgramImage;
+
+pub mod errors;
+pub mod object;
+
+/// The read-only data of each object starts at a multiple of this,
+/// so floats and `.align` up to 8 bytes stay aligned once the objects are merged
+const RO_ALIGNMENT: usize = 8;
+
+/// Where the sections of one object start in the merged sections
+struct Placement {
+    ro_start: usize,
+    code_start: usize,
+}
+
+/// Links objects into a PIE program the VM can run.
+/// The sections of the objects are laid out in order, so the program starts with the code of the first one
+pub fn link(objects: &[ObjectFile]) -> Result<Vec<u8>, Vec<LinkerError>> {
+    if objects.is_empty() {
+        return Err(vec![LinkerError::NoObjects]);
+    }
+
+    let mut ro_data = vec![];
+    let mut code = vec![];
+    let mut placements = vec![];
+    for object in objects {
+        while ro_data.len() % RO_ALIGNMENT != 0 {
+            ro_data.push(0);
+        }
+        placements.push(Placement { ro_start: ro_data.len(), code_start: code.len() });
+        ro_data.extend_from_slice(&object.ro_data);
+        code.extend_from_slice(&object.code);
+    }
+
+    // Data labels are offsets into the read-only section, code labels are absolute addresses,
+    // which count the header and the whole read-only section
+    let code_base = PIE_HEADER_LENGTH + ro_data.len();
+    let address = |placement: &Placement, section: Section| match section {
+        Section::ReadOnly => placement.ro_start as i64,
+        Section::Code => (code_base + placement.code_start) as i64,
+    };
+
+    let mut errors = vec![];
+    let mut symbols: HashMap<&str, (i64, &str)> = HashMap::new();
+    for (object, placement) in objects.iter().zip(&placements) {
+        for symbol in &object.exports {
+            if let Some(&(_, first)) = symbols.get(symbol.name.as_str()) {
+                errors.push(LinkerError::DuplicateSymbol {
+                    name: symbol.name.clone(),
+                    first: first.to_string(),
+                    second: object.name.clone(),
+                });
+                continue;
+            }
+            let value = address(placement, symbol.section) + i64::from(symbol.offset);
+            symbols.insert(&symbol.name, (value, &object.name));
+        }
+    }
+
+    for (object, placement) in objects.iter().zip(&placements) {
+        let undefined = |name: &str| LinkerError::UndefinedSymbol { name: name.to_string(), object: object.name.clone() };
+        for name in &object.imports {
+            if !symbols.contains_key(name.as_str()) {
+                errors.push(undefined(name));
+            }
+        }
+
+        for relocation in &object.relocations {
+            let target = match relocation.target {
+                RelocationTarget::Section(section) => address(placement, section),
+                RelocationTarget::Symbol(ref name) => match symbols.get(name.as_str()) {
+                    Some(&(value, _)) => value,
+                    None => {
+                        // Already reported if the object imports it
+                        if !errors.contains(&undefined(name)) {
+                            errors.push(undefined(name));
+                        }
+                        continue;
+                    }
+                },
+            };
+            let section = match relocation.section {
+                Section::ReadOnly => &mut ro_data[placement.ro_start..placement.ro_start + object.ro_data.len()],
+                Section::Code => &mut code[placement.code_start..placement.code_start + object.code.len()],
+            };
+            if let Err(e) = write_field(section, relocation, target + relocation.addend, &object.name) {
+                errors.push(e);
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(ProgramImage::new(ro_data, code).to_bytes())
+}
+
+/// Writes `value` into the field of `section` that `relocation` points at
+fn write_field(section: &mut [u8], relocation: &Relocation, value: i64, object: &str) -> Result<(), LinkerError> {
+    let out_of_range = |bits| LinkerError::RelocationOutOfRange { value, bits, object: object.to_string() };
+    let bytes = match relocation.field {
+        Field::Operand16 => {
+            if value < 0 || value > i64::from(u16::MAX) {
+                return Err(out_of_range(16));
+            }
+            vec![(value >> 8) as u8, value as u8]
+        }
+        Field::Data { width } => {
+            let bits = 8 * width;
+            if value < -(1 << (bits - 1)) || value >= 1 << bits {
+                return Err(out_of_range(bits));
+            }
+            let mut bytes = vec![];
+            bytes.write_uint::<LittleEndian>(value as u64 & ((1 << bits) - 1), width as usize).unwrap();
+            bytes
+        }
+    };
+
+    let offset = relocation.offset as usize;
+    match section.get_mut(offset..offset + bytes.len()) {
+        Some(field) => {
+            field.copy_from_slice(&bytes);
+            Ok(())
+        }
+        None => Err(LinkerError::RelocationOutOfBounds { offset: relocation.offset, object: object.to_string() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+    use vm::VM;
+
+    fn assemble(name: &str, source: &str) -> ObjectFile {
+        let mut asm = Assembler::new();
+        asm.set_file_name(name);
+        asm.assemble_object(source).unwrap()
+    }
+
+    #[test]
+    fn test_link() {
+        let main = assemble("main.iasm", ".extern greet\n.data\npad: .asciiz 'abc'\n.code\ncall @greet\nhlt\n");
+        let lib = assemble(
+            "lib.iasm",
+            ".data\n.global message\nmessage: .asciiz 'Hi'\ntable: .word @greet @message+1\n\
+             .code\n.global greet\ngreet: load $1 @message\nload $2 @table+4\nloadf64 $3 #2.5\nret\n",
+        );
+        assert_eq!(main.imports, vec!["greet".to_string()]);
+        assert_eq!(lib.exports.len(), 2);
+
+        let program = link(&[main, lib]).unwrap();
+        let image = ProgramImage::parse(&program).unwrap();
+        // The library's data starts at 8, its code after the 8 bytes of main's code
+        assert_eq!(&image.ro_data[8..19], &[72, 105, 0, 99, 0, 0, 0, 9, 0, 0, 0]);
+
+        let mut vm = VM::new();
+        vm.load_program(program).unwrap();
+        vm.run();
+        assert_eq!(&vm.registers[1..3], &[8, 15]);
+        assert_eq!(vm.float_registers[3], 2.5);
+    }
+
+    #[test]
+    fn test_link_errors() {
+        assert_eq!(link(&[]), Err(vec![LinkerError::NoObjects]));
+
+        let main = assemble("main.iasm", ".extern missing\n.data\n.code\ncall @missing\nhlt\n");
+        let first = assemble("a.iasm", ".data\n.code\n.global f\nf: ret\n");
+        let second = assemble("b.iasm", ".data\n.code\n.global f\nf: ret\n");
+        assert_eq!(
+            link(&[main, first, second]),
+            Err(vec![
+                LinkerError::DuplicateSymbol { name: "f".to_string(), first: "a.iasm".to_string(), second: "b.iasm".to_string() },
+                LinkerError::UndefinedSymbol { name: "missing".to_string(), object: "main.iasm".to_string() },
+            ])
+        );
+
+        let main = assemble("main.iasm", ".extern far\n.data\n.code\nload $0 @far+65535\nhlt\n");
+        let lib = assemble("lib.iasm", ".data\n.code\n.global far\nfar: ret\n");
+        assert_eq!(
+            link(&[main, lib]),
+            Err(vec![LinkerError::RelocationOutOfRange { value: 65535 + 72, bits: 16, object: "main.iasm".to_string() }])
+        );
+    }
+}
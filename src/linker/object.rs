@@ -0,0 +1,252 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use linker::errors::LinkerError;
+use std::io::{Cursor, Read};
+
+/// Magic number at the start of an object file, `EOBJ`
+pub const OBJECT_HEADER_PREFIX: [u8; 4] = [0x45, 0x4f, 0x42, 0x4a];
+
+/// The sections of a program that labels point into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Section {
+    ReadOnly,
+    Code,
+}
+
+/// How a value the linker writes is stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    /// A 16 bits operand of an instruction, high byte first
+    Operand16,
+    /// A value of `.byte`, `.half` or `.word`, `width` bytes little endian
+    Data { width: u8 },
+}
+
+/// What the value of a relocation is computed from
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelocationTarget {
+    /// Where a section of the same object ends up
+    Section(Section),
+    /// A symbol exported by another object
+    Symbol(String),
+}
+
+/// A field whose value depends on where the objects end up, written by the linker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    /// The section the field is in
+    pub section: Section,
+    /// Where the field is in its section
+    pub offset: u32,
+    pub field: Field,
+    pub target: RelocationTarget,
+    /// Added to the address of the target
+    pub addend: i64,
+}
+
+/// A label other objects can use, declared with `.global`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportedSymbol {
+    pub name: String,
+    pub section: Section,
+    /// Where the label is in its section
+    pub offset: u32,
+}
+
+/// A separately assembled module, made by `Assembler::assemble_object`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ObjectFile {
+    /// The file the object was assembled from, used when reporting errors
+    pub name: String,
+    pub ro_data: Vec<u8>,
+    pub code: Vec<u8>,
+    pub exports: Vec<ExportedSymbol>,
+    /// The symbols declared with `.extern`, which other objects have to export
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl ObjectFile {
+    /// Writes the object in the format `parse` reads, all numbers little endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = OBJECT_HEADER_PREFIX.to_vec();
+        write_string(&mut bytes, &self.name);
+        bytes.write_u32::<LittleEndian>(self.ro_data.len() as u32).unwrap();
+        bytes.extend_from_slice(&self.ro_data);
+        bytes.write_u32::<LittleEndian>(self.code.len() as u32).unwrap();
+        bytes.extend_from_slice(&self.code);
+
+        bytes.write_u32::<LittleEndian>(self.exports.len() as u32).unwrap();
+        for symbol in &self.exports {
+            write_string(&mut bytes, &symbol.name);
+            bytes.push(section_code(symbol.section));
+            bytes.write_u32::<LittleEndian>(symbol.offset).unwrap();
+        }
+
+        bytes.write_u32::<LittleEndian>(self.imports.len() as u32).unwrap();
+        for name in &self.imports {
+            write_string(&mut bytes, name);
+        }
+
+        bytes.write_u32::<LittleEndian>(self.relocations.len() as u32).unwrap();
+        for relocation in &self.relocations {
+            bytes.push(section_code(relocation.section));
+            bytes.write_u32::<LittleEndian>(relocation.offset).unwrap();
+            bytes.push(match relocation.field {
+                Field::Operand16 => 0,
+                Field::Data { width } => width,
+            });
+            match relocation.target {
+                RelocationTarget::Section(section) => bytes.push(section_code(section)),
+                RelocationTarget::Symbol(ref name) => {
+                    bytes.push(2);
+                    write_string(&mut bytes, name);
+                }
+            }
+            bytes.write_i64::<LittleEndian>(relocation.addend).unwrap();
+        }
+        bytes
+    }
+
+    /// Reads an object written by `to_bytes`
+    pub fn parse(bytes: &[u8]) -> Result<Self, LinkerError> {
+        if !bytes.starts_with(&OBJECT_HEADER_PREFIX) {
+            return Err(LinkerError::InvalidObject { reason: "it does not start with the object header" });
+        }
+        let mut rdr = Cursor::new(&bytes[OBJECT_HEADER_PREFIX.len()..]);
+        let object = read_object(&mut rdr).ok_or(LinkerError::InvalidObject { reason: "it is cut short or corrupted" })?;
+        if rdr.position() as usize != bytes.len() - OBJECT_HEADER_PREFIX.len() {
+            return Err(LinkerError::InvalidObject { reason: "there are bytes after its end" });
+        }
+        Ok(object)
+    }
+}
+
+fn read_object(rdr: &mut Cursor<&[u8]>) -> Option<ObjectFile> {
+    let name = read_string(rdr)?;
+    let ro_data = read_bytes(rdr)?;
+    let code = read_bytes(rdr)?;
+
+    let mut exports = vec![];
+    for _ in 0..rdr.read_u32::<LittleEndian>().ok()? {
+        exports.push(ExportedSymbol {
+            name: read_string(rdr)?,
+            section: read_section(rdr)?,
+            offset: rdr.read_u32::<LittleEndian>().ok()?,
+        });
+    }
+
+    let mut imports = vec![];
+    for _ in 0..rdr.read_u32::<LittleEndian>().ok()? {
+        imports.push(read_string(rdr)?);
+    }
+
+    let mut relocations = vec![];
+    for _ in 0..rdr.read_u32::<LittleEndian>().ok()? {
+        let section = read_section(rdr)?;
+        let offset = rdr.read_u32::<LittleEndian>().ok()?;
+        let field = match rdr.read_u8().ok()? {
+            0 => Field::Operand16,
+            width @ 1 | width @ 2 | width @ 4 => Field::Data { width },
+            _ => return None,
+        };
+        let target = match rdr.read_u8().ok()? {
+            2 => RelocationTarget::Symbol(read_string(rdr)?),
+            code => RelocationTarget::Section(section_from_code(code)?),
+        };
+        let addend = rdr.read_i64::<LittleEndian>().ok()?;
+        relocations.push(Relocation { section, offset, field, target, addend });
+    }
+
+    Some(ObjectFile { name, ro_data, code, exports, imports, relocations })
+}
+
+fn section_code(section: Section) -> u8 {
+    match section {
+        Section::ReadOnly => 0,
+        Section::Code => 1,
+    }
+}
+
+fn section_from_code(code: u8) -> Option<Section> {
+    match code {
+        0 => Some(Section::ReadOnly),
+        1 => Some(Section::Code),
+        _ => None,
+    }
+}
+
+fn read_section(rdr: &mut Cursor<&[u8]>) -> Option<Section> {
+    section_from_code(rdr.read_u8().ok()?)
+}
+
+/// A string is its length in a u16, then its UTF-8 bytes
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.write_u16::<LittleEndian>(string.len() as u16).unwrap();
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+fn read_string(rdr: &mut Cursor<&[u8]>) -> Option<String> {
+    let length = rdr.read_u16::<LittleEndian>().ok()?;
+    let mut bytes = vec![0; length as usize];
+    rdr.read_exact(&mut bytes).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn read_bytes(rdr: &mut Cursor<&[u8]>) -> Option<Vec<u8>> {
+    let length = rdr.read_u32::<LittleEndian>().ok()?;
+    let remaining = rdr.get_ref().len() - rdr.position() as usize;
+    if length as usize > remaining {
+        return None;
+    }
+    let mut bytes = vec![0; length as usize];
+    rdr.read_exact(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_round_trip() {
+        let object = ObjectFile {
+            name: "lib.iasm".to_string(),
+            ro_data: vec![72, 105, 0],
+            code: vec![0, 1, 0, 0, 5, 0, 0, 0],
+            exports: vec![ExportedSymbol { name: "greet".to_string(), section: Section::Code, offset: 0 }],
+            imports: vec!["main".to_string()],
+            relocations: vec![
+                Relocation {
+                    section: Section::Code,
+                    offset: 2,
+                    field: Field::Operand16,
+                    target: RelocationTarget::Section(Section::ReadOnly),
+                    addend: 0,
+                },
+                Relocation {
+                    section: Section::ReadOnly,
+                    offset: 0,
+                    field: Field::Data { width: 4 },
+                    target: RelocationTarget::Symbol("main".to_string()),
+                    addend: -4,
+                },
+            ],
+        };
+        let bytes = object.to_bytes();
+        assert_eq!(&bytes[..4], &OBJECT_HEADER_PREFIX);
+        assert_eq!(ObjectFile::parse(&bytes), Ok(object));
+    }
+
+    #[test]
+    fn test_parse_bad_object() {
+        assert_eq!(
+            ObjectFile::parse(&[0x45, 0x50, 0x49, 0x45]),
+            Err(LinkerError::InvalidObject { reason: "it does not start with the object header" })
+        );
+        let mut bytes = ObjectFile::default().to_bytes();
+        bytes.pop();
+        assert_eq!(ObjectFile::parse(&bytes), Err(LinkerError::InvalidObject { reason: "it is cut short or corrupted" }));
+        bytes.extend_from_slice(&[0, 0]);
+        assert_eq!(ObjectFile::parse(&bytes), Err(LinkerError::InvalidObject { reason: "there are bytes after its end" }));
+    }
+}
@@ -64,8 +64,8 @@ impl Client {
             loop {
                 match chan.recv() {
                     Ok(msg) => {
-                        writer.write_all(msg.as_bytes());
-                        writer.flush();
+                        let _ = writer.write_all(msg.as_bytes());
+                        let _ = writer.flush();
                     }
                     Err(_e) => {}
                 }
@@ -82,7 +82,6 @@ impl Client {
         loop {
             match self.reader.read_line(&mut buf) {
                 Ok(_) => {
-                    buf.trim_right();
                     self.repl.run_single(&buf);
                 }
                 Err(e) => {
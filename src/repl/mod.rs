@@ -1,6 +1,8 @@
 use assembler::Assembler;
-use assembler::program_parsers::parse_program;
-use nom::types::CompleteStr;
+use assembler::program_parsers::{parse_program, Program};
+use assembler::source::DEFAULT_FILE_NAME;
+use assembler::listing::symbol_address;
+use assembler::symbols::Symbol;
 use repl::command_parser::CommandParser;
 use scheduler::Scheduler;
 use std;
@@ -12,13 +14,12 @@ use std::num::ParseIntError;
 use std::path::Path;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::mpsc;
-use std::u8;
 use vm::VM;
 
 pub mod command_parser;
 
-pub static REMOTE_BANNER: &'static str = "Welcome to Iridium! Let's be productive!";
-pub static PROMPT: &'static str = ">>> ";
+pub static REMOTE_BANNER: &str = "Welcome to Iridium! Let's be productive!";
+pub static PROMPT: &str = ">>> ";
 const COMMAND_PREFIX: char = '!';
 
 /// Core structure for the REPL for the Assembler
@@ -69,20 +70,19 @@ impl REPL {
             let buffer = buffer.trim();
             // commands are start with `!`
             if buffer.starts_with("!") {
-                self.execute_command(&buffer);
+                self.execute_command(buffer);
             } else {
-                let program = match parse_program(buffer.into()) {
-                    Ok((_, program)) => program,
-                    Err(e) => {
-                        self.send_message(format!("Unable to parse input: {:?}", e));
+                let program = match parse_program(buffer, DEFAULT_FILE_NAME) {
+                    Ok(program) => program,
+                    Err(errors) => {
+                        for error in errors {
+                            self.send_message(error.render());
+                        }
                         self.send_prompt();
                         continue;
                     }
                 };
-                self.vm
-                    .program
-                    .append(&mut program.to_bytes(&self.asm.symbols));
-                self.vm.run_once();
+                self.run_program(&program);
             }
         }
     }
@@ -90,49 +90,64 @@ impl REPL {
     /// Sends single cmd to remote
     pub fn run_single(&mut self, buf: &str) -> Option<String> {
         if buf.starts_with(COMMAND_PREFIX) {
-            self.execute_command(&buf);
+            self.execute_command(buf);
             None
         } else {
-            let program = match parse_program(CompleteStr(buf)) {
-                Ok((_remainder, program)) => Some(program),
-                Err(err) => {
-                    self.send_message(format!("Unable to parse input: {:?}", err));
+            let program = match parse_program(buf, DEFAULT_FILE_NAME) {
+                Ok(program) => Some(program),
+                Err(errors) => {
+                    for error in errors {
+                        self.send_message(error.render());
+                    }
                     self.send_prompt();
                     None
                 }
             };
 
-            program.map(|p| {
-                let mut bytes = p.to_bytes(&self.asm.symbols);
-                self.vm.program.append(&mut bytes);
-                self.vm.run_once();
-                p
-            });
+            if let Some(p) = program {
+                self.run_program(&p);
+            }
             None
         }
     }
 
+    /// Appends the instructions just entered to the VM's program and executes them,
+    /// stopping at the first one that fails, which is taken back out of the program
+    fn run_program(&mut self, program: &Program) {
+        match program.to_bytes(&self.asm.symbols) {
+            Ok(mut bytes) => {
+                self.vm.program.append(&mut bytes);
+                // A line can be several instructions, such as a wide `load`, which is a `load` and a `loadhi`.
+                // The VM reports a stop once its pc reaches the end of what was appended
+                loop {
+                    match self.vm.step() {
+                        Ok(None) => {}
+                        Ok(Some(_)) => break,
+                        Err(e) => {
+                            self.send_message(format!("VM error: {}", e));
+                            // Drop what could not run, so the next line starts where this one stopped
+                            let pc = self.vm.pc();
+                            self.vm.program.truncate(pc);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => self.send_message(format!("Unable to assemble input:\n{}", e.render())),
+        }
+    }
+
     /// Sends message to remote
     pub fn send_message(&mut self, msg: String) {
-        match &self.tx_pipe {
-            Some(pipe) => {
-                match pipe.send(msg) {
-                    Ok(_) => {}
-                    Err(_e) => {}
-                };
-            }
-            None => {}
+        if let Some(pipe) = &self.tx_pipe {
+            let _ = pipe.send(msg);
         }
     }
 
     /// Sends prompt to remote
     pub fn send_prompt(&mut self) {
-        match &self.tx_pipe {
-            Some(pipe) => match pipe.send(PROMPT.to_owned()) {
-                Ok(_) => {}
-                Err(_e) => {}
-            },
-            None => {}
+        if let Some(pipe) = &self.tx_pipe {
+            let _ = pipe.send(PROMPT.to_owned());
         }
     }
 
@@ -144,7 +159,7 @@ impl REPL {
         let mut results: Vec<u8> = vec![];
 
         for hex_string in split {
-            let byte = u8::from_str_radix(&hex_string, 16);
+            let byte = u8::from_str_radix(hex_string, 16);
             match byte {
                 Ok(result) => {
                     results.push(result);
@@ -168,6 +183,7 @@ impl REPL {
             "!clear_registers" => self.clear_registers(&args[1..]),
             "!registers" => self.registers(&args[1..]),
             "!symbols" => self.symbols(&args[1..]),
+            "!stack" => self.stack(&args[1..]),
             "!load_file" => self.load_file(&args[1..]),
             "!spawn" => self.spawn(&args[1..]),
             _ => {
@@ -195,7 +211,7 @@ impl REPL {
         self.send_message("Listing instructions currently in VM's program vector: ".to_string());
         let mut results = vec![];
         for instruction in &self.vm.program {
-            results.push(instruction.clone())
+            results.push(*instruction)
         }
         self.send_message(format!("{:#?}", results));
         self.send_message("End of Program Listing".to_string());
@@ -219,44 +235,51 @@ impl REPL {
         self.send_message("Listing registers and all contents:".to_string());
         let mut results = vec![];
         for register in &self.vm.registers {
-            results.push(register.clone());
+            results.push(*register);
         }
         self.send_message(format!("{:#?}", results));
         self.send_message("End of Register Listing".to_string());
         self.send_prompt();
     }
 
+    fn stack(&mut self, _args: &[&str]) {
+        self.send_message("Listing stack slots, bottom first:".to_string());
+        let results = self.vm.stack().to_vec();
+        self.send_message(format!("{:#?}", results));
+        self.send_message("End of Stack Listing".to_string());
+        self.send_prompt();
+    }
+
     fn symbols(&mut self, _args: &[&str]) {
-//        todo: fix it
-//        let mut results = vec![];
-//        for symbol in &self.asm.symbols.symbols {
-//            results.push(symbol.clone());
-//        }
-//        self.send_message("Listing symbols table:".to_string());
-//        self.send_message(format!("{:#?}", results));
-//        self.send_message("End of Symbols Listing".to_string());
-//        self.send_prompt();
+        let results: Vec<String> = self.asm.symbols.iter().map(describe_symbol).collect();
+        self.send_message("Listing symbols table:".to_string());
+        for result in results {
+            self.send_message(result);
+        }
+        self.send_message("End of Symbols Listing".to_string());
+        self.send_prompt();
     }
 
     fn load_file(&mut self, _args: &[&str]) {
         let contents = self.get_data_from_load();
         if let Some(contents) = contents {
             match self.asm.assemble(&contents) {
-                Ok(mut program) => {
+                Ok(program) => {
                     self.send_message("Sending assembled program to VM".to_string());
-                    self.vm.program.append(&mut program);
-                    self.vm.run();
+                    match self.vm.load_program(program) {
+                        Ok(_) => {
+                            self.vm.run();
+                        }
+                        Err(e) => self.send_message(format!("Unable to load program: {}", e)),
+                    }
                 },
                 Err(errs) => {
                     for err in errs {
-                        self.send_message(format!("Unable to parse input: {}", err));
+                        self.send_message(err.render());
                         self.send_prompt();
                     }
-                    return;
                 }
             }
-        } else {
-            return;
         }
     }
 
@@ -265,21 +288,22 @@ impl REPL {
         self.send_message(format!("Loaded contents: {:#?}", contents));
         if let Some(contents) = contents {
             match self.asm.assemble(&contents) {
-                Ok(mut program) => {
+                Ok(program) => {
                     self.send_message("Sending assembled program to VM".to_string());
-                    self.vm.program.append(&mut program);
-                    self.scheduler.get_thread(self.vm.clone());
+                    match self.vm.load_program(program) {
+                        Ok(_) => {
+                            self.scheduler.get_thread(self.vm.clone());
+                        }
+                        Err(e) => self.send_message(format!("Unable to load program: {}", e)),
+                    }
                 },
                 Err(errs) => {
                     for err in errs {
-                        self.send_message(format!("Unable to parse input: {}", err));
+                        self.send_message(err.render());
                         self.send_prompt();
                     }
-                    return;
                 }
             }
-        } else {
-            return;
         }
     }
 
@@ -312,4 +336,43 @@ impl REPL {
     }
 }
 
+/// One line of `!symbols`: the name, the kind, the address or value, the size of data,
+/// the visibility and where the symbol was declared
+fn describe_symbol(symbol: &Symbol) -> String {
+    format!(
+        "{:<20} {:<10} {:>8} {:>6} {:<6} {}",
+        symbol.name(),
+        symbol.symbol_type().to_string(),
+        symbol_address(symbol),
+        symbol.size().map_or("-".to_string(), |size| size.to_string()),
+        symbol.visibility().to_string(),
+        symbol.location().map_or("-".to_string(), |location| location.to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn test_run_every_appended_instruction() {
+        let mut repl = REPL::new();
+        repl.run_single("load $0 #70000");
+        assert_eq!(repl.vm.registers[0], 70000);
+        repl.run_single("load $1 #7");
+        assert_eq!(repl.vm.registers[1], 7);
+        // Pseudo-instructions run like they do in a file
+        repl.run_single("li $5 #70000");
+        assert_eq!(repl.vm.registers[5], 70000);
+        repl.run_single("clr $5");
+        assert_eq!(repl.vm.registers[5], 0);
+
+        // A failing instruction is reported once, and the line after it still runs
+        repl.run_single("div $1 $2 $3");
+        let received: Vec<String> = repl.rx_pipe.as_ref().unwrap().try_iter().collect();
+        assert_eq!(received, vec!["VM error: Attempted to divide by zero".to_string()]);
+        repl.run_single("load $4 #9");
+        assert_eq!(repl.vm.registers[4], 9);
+        assert_eq!(repl.rx_pipe.as_ref().unwrap().try_iter().count(), 0);
+    }
+}
@@ -5,10 +5,19 @@ use vm::{VM, VMEvent};
 /// Scheduler can handle with multi-threads
 #[derive(Debug)]
 pub struct Scheduler {
+    // Reserved for handing out unique PIDs to spawned VMs
+    #[allow(dead_code)]
     next_pid: u32,
+    #[allow(dead_code)]
     max_pid: u32,
 }
 
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Scheduler {
     /// Creates a Scheduler for repl
     pub fn new() -> Self {
@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fmt;
+
+/// Errors raised while the VM executes a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    InvalidHeader,
+    InvalidRoSectionLength { length: usize },
+    PcOutOfBounds { pc: usize },
+    InvalidJumpTarget { target: i64 },
+    InvalidRegister { register: u8 },
+    IllegalOpcode { opcode: u8 },
+    DivisionByZero,
+    RoDataOutOfBounds { offset: usize },
+    InvalidUtf8 { offset: usize },
+    InvalidAllocation { size: i32 },
+    HeapOverflow { size: usize },
+    StackOverflow,
+    StackUnderflow,
+    HeapOutOfBounds { address: i64, size: usize },
+    InvalidConversionMode { mode: u8 },
+}
+
+impl VmError {
+    /// The exit code reported in `VMEventType::Crash`
+    pub fn code(&self) -> u32 {
+        match *self {
+            VmError::InvalidHeader => 1,
+            VmError::PcOutOfBounds { .. } => 2,
+            VmError::InvalidJumpTarget { .. } => 3,
+            VmError::InvalidRegister { .. } => 4,
+            VmError::IllegalOpcode { .. } => 5,
+            VmError::DivisionByZero => 6,
+            VmError::RoDataOutOfBounds { .. } => 7,
+            VmError::InvalidUtf8 { .. } => 8,
+            VmError::InvalidAllocation { .. } => 9,
+            VmError::HeapOverflow { .. } => 10,
+            VmError::InvalidRoSectionLength { .. } => 11,
+            VmError::StackOverflow => 12,
+            VmError::StackUnderflow => 13,
+            VmError::HeapOutOfBounds { .. } => 14,
+            VmError::InvalidConversionMode { .. } => 15,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmError::InvalidHeader => {
+                f.write_str("The program does not start with a valid PIE header")
+            }
+            VmError::InvalidRoSectionLength { length } => {
+                f.write_str(&format!("The header declares a read-only section of {} bytes, but the program is shorter", length))
+            }
+            VmError::PcOutOfBounds { pc } => {
+                f.write_str(&format!("Tried to read past the end of the program at byte {}", pc))
+            }
+            VmError::InvalidJumpTarget { target } => {
+                f.write_str(&format!("Jump target {} is outside of the program", target))
+            }
+            VmError::InvalidRegister { register } => {
+                f.write_str(&format!("Register ${} does not exist", register))
+            }
+            VmError::IllegalOpcode { opcode } => {
+                f.write_str(&format!("Illegal opcode {} encountered", opcode))
+            }
+            VmError::DivisionByZero => {
+                f.write_str("Attempted to divide by zero")
+            }
+            VmError::RoDataOutOfBounds { offset } => {
+                f.write_str(&format!("Read-only section has no string at offset {}", offset))
+            }
+            VmError::InvalidUtf8 { offset } => {
+                f.write_str(&format!("String at read-only offset {} is not valid UTF-8", offset))
+            }
+            VmError::InvalidAllocation { size } => {
+                f.write_str(&format!("Cannot allocate {} bytes on the heap", size))
+            }
+            VmError::HeapOverflow { size } => {
+                f.write_str(&format!("Heap would grow to {} bytes, which exceeds its limit", size))
+            }
+            VmError::StackOverflow => {
+                f.write_str("Pushed onto a full stack")
+            }
+            VmError::StackUnderflow => {
+                f.write_str("Popped from an empty stack")
+            }
+            VmError::HeapOutOfBounds { address, size } => {
+                f.write_str(&format!("Accessed {} bytes at heap address {}, outside of the heap", size, address))
+            }
+            VmError::InvalidConversionMode { mode } => {
+                f.write_str(&format!("Float to integer conversion mode {} does not exist", mode))
+            }
+        }
+    }
+}
+
+impl Error for VmError {
+    fn description(&self) -> &str {
+        match self {
+            VmError::InvalidHeader => "The program does not start with a valid PIE header",
+            VmError::InvalidRoSectionLength { .. } => "The read-only section is longer than the program",
+            VmError::PcOutOfBounds { .. } => "Tried to read past the end of the program",
+            VmError::InvalidJumpTarget { .. } => "Jump target is outside of the program",
+            VmError::InvalidRegister { .. } => "Register does not exist",
+            VmError::IllegalOpcode { .. } => "Illegal opcode encountered",
+            VmError::DivisionByZero => "Attempted to divide by zero",
+            VmError::RoDataOutOfBounds { .. } => "Read-only section access out of bounds",
+            VmError::InvalidUtf8 { .. } => "String in read-only section is not valid UTF-8",
+            VmError::InvalidAllocation { .. } => "Invalid heap allocation size",
+            VmError::HeapOverflow { .. } => "Heap exceeds its limit",
+            VmError::StackOverflow => "Pushed onto a full stack",
+            VmError::StackUnderflow => "Popped from an empty stack",
+            VmError::HeapOutOfBounds { .. } => "Heap access out of bounds",
+            VmError::InvalidConversionMode { .. } => "Float to integer conversion mode does not exist",
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use assembler::{PIE_HEADER_LENGTH, PIE_HEADER_PREFIX};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
+use vm::errors::VmError;
+
+/// A PIE program split into its sections:
+/// a 64 bytes header, the read-only section and the executable code
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProgramImage {
+    /// Contains the read-only section data
+    pub ro_data: Vec<u8>,
+    /// The bytecode that gets executed
+    pub code: Vec<u8>,
+}
+
+impl ProgramImage {
+    /// Creates an image from its sections
+    pub fn new(ro_data: Vec<u8>, code: Vec<u8>) -> Self {
+        Self { ro_data, code }
+    }
+
+    /// Splits raw bytes into header, read-only section and code
+    pub fn parse(bytes: &[u8]) -> Result<Self, VmError> {
+        if bytes.len() < PIE_HEADER_LENGTH || bytes[0..4] != PIE_HEADER_PREFIX {
+            return Err(VmError::InvalidHeader);
+        }
+
+        // The length of the read-only section is stored right after the magic number
+        let mut rdr = Cursor::new(&bytes[4..8]);
+        let ro_length = rdr.read_u32::<LittleEndian>().unwrap() as usize;
+        let code_offset = PIE_HEADER_LENGTH + ro_length;
+        if code_offset > bytes.len() {
+            return Err(VmError::InvalidRoSectionLength { length: ro_length });
+        }
+
+        Ok(Self {
+            ro_data: bytes[PIE_HEADER_LENGTH..code_offset].to_vec(),
+            code: bytes[code_offset..].to_vec(),
+        })
+    }
+
+    /// The absolute offset of the first instruction
+    pub fn code_offset(&self) -> usize {
+        PIE_HEADER_LENGTH + self.ro_data.len()
+    }
+
+    /// Writes the header and both sections back into a single vector
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = PIE_HEADER_PREFIX.to_vec();
+        bytes.write_u32::<LittleEndian>(self.ro_data.len() as u32).unwrap();
+        bytes.resize(PIE_HEADER_LENGTH, 0);
+        bytes.extend_from_slice(&self.ro_data);
+        bytes.extend_from_slice(&self.code);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_image() {
+        let image = ProgramImage::new(vec![72, 105, 0], vec![5, 0, 0, 0]);
+        let bytes = image.to_bytes();
+        assert_eq!(bytes.len(), PIE_HEADER_LENGTH + 7);
+        assert_eq!(bytes[4], 3);
+        let parsed = ProgramImage::parse(&bytes).unwrap();
+        assert_eq!(parsed, image);
+        assert_eq!(parsed.code_offset(), PIE_HEADER_LENGTH + 3);
+    }
+
+    #[test]
+    fn test_parse_bad_image() {
+        assert_eq!(ProgramImage::parse(&[0x45, 0x50]), Err(VmError::InvalidHeader));
+
+        let mut bytes = ProgramImage::new(vec![0; 4], vec![]).to_bytes();
+        bytes.truncate(PIE_HEADER_LENGTH + 2);
+        assert_eq!(
+            ProgramImage::parse(&bytes),
+            Err(VmError::InvalidRoSectionLength { length: 4 })
+        );
+    }
+}
@@ -0,0 +1,1416 @@
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::prelude::*;
+use instruction::Opcode;
+use num_cpus;
+use std;
+use std::f64;
+use std::ops::Range;
+use uuid::Uuid;
+use vm::errors::VmError;
+use vm::image::ProgramImage;
+
+pub mod errors;
+pub mod image;
+
+/// The largest heap a program may grow with `ALOC`
+pub const MAX_HEAP_SIZE: usize = 64 * 1024 * 1024;
+/// The number of 64 bits slots in the stack segment
+pub const MAX_STACK_SIZE: usize = 64 * 1024;
+/// The number of integer registers, and of float registers
+pub const REGISTER_COUNT: usize = 32;
+
+/// Virtual machine struct that will execute bytecode
+#[derive(Default, Clone)]
+pub struct VM {
+    /// Array that simulates having hardware registers
+    pub registers: [i32; REGISTER_COUNT],
+    pub float_registers: [f64; REGISTER_COUNT],
+    /// Program counter that tracks which byte is being executed
+    pc: usize,
+    /// The bytecode of the program being run
+    pub program: Vec<u8>,
+    /// Used for heap memory
+    heap: Vec<u8>,
+    /// Stack segment shared by `CALL`/`RET` return addresses and `PUSH`/`POP` values
+    stack: Vec<u64>,
+    /// Contains the remainder of modulo division ops
+    remainder: usize,
+    /// Contains the result of the last comparison operation
+    equal_flag: bool,
+    /// Contains the read-only section data
+    ro_data: Vec<u8>,
+    /// Is a unique, randomly generated UUID for identifying this VM
+    id: Uuid,
+    /// VMEvent recivie events
+    events: Vec<VMEvent>,
+    /// The core number of CPU
+    pub logical_cores: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum VMEventType {
+    Start,
+    GracefulStop {code: u32},
+    Crash {code: u32, error: VmError},
+}
+
+#[derive(Debug, Clone)]
+pub struct VMEvent {
+    event: VMEventType,
+    at: DateTime<Utc>,
+    application_id: Uuid,
+}
+
+impl VMEvent {
+    /// What happened
+    pub fn event(&self) -> &VMEventType {
+        &self.event
+    }
+
+    /// When it happened
+    pub fn at(&self) -> DateTime<Utc> {
+        self.at
+    }
+
+    /// The VM it happened in
+    pub fn application_id(&self) -> Uuid {
+        self.application_id
+    }
+}
+
+impl VM {
+    /// Creates and returns a new VM
+    pub fn new() -> VM {
+        VM {
+            registers: [0; REGISTER_COUNT],
+            float_registers: [0.0; REGISTER_COUNT],
+            program: vec![],
+            ro_data: vec![],
+            heap: vec![],
+            stack: vec![],
+            pc: 0,
+            remainder: 0,
+            equal_flag: false,
+            id: Uuid::new_v4(),
+            events: Vec::new(),
+            logical_cores: num_cpus::get(),
+        }
+    }
+
+    /// Wraps execution in a loop so it will continue to run until done or there is an error
+    /// executing instructions.
+    pub fn run(&mut self) -> Vec<VMEvent> {
+        self.push_event(VMEventType::Start);
+
+        match self.execute_program() {
+            Ok(code) => self.push_event(VMEventType::GracefulStop { code }),
+            Err(error) => self.crash(error),
+        }
+
+        self.events.clone()
+    }
+
+    /// Executes one instruction. Meant to allow for more controlled execution of the VM.
+    /// A failing instruction is recorded as a `VMEventType::Crash`.
+    pub fn run_once(&mut self) {
+        if let Err(error) = self.step() {
+            self.crash(error);
+        }
+    }
+
+    /// Executes one instruction and reports what went wrong, if anything.
+    /// Returns `Ok(Some(code))` once the program has stopped.
+    /// A failing instruction leaves the PC at its first byte, not partway through its operands.
+    pub fn step(&mut self) -> Result<Option<u32>, VmError> {
+        let start = self.pc;
+        let result = self.execute_instruction();
+        if result.is_err() {
+            self.pc = start;
+        }
+        result
+    }
+
+    /// Returns the offset of the next byte to execute
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Returns the stack segment, bottom first
+    pub fn stack(&self) -> &[u64] {
+        &self.stack
+    }
+
+    /// Returns all the events recorded so far
+    pub fn events(&self) -> &[VMEvent] {
+        &self.events
+    }
+
+    /// Loads a PIE image, replacing whatever program the VM had.
+    /// The read-only section is copied out of the image so `PRTS` can find its strings.
+    pub fn load_program(&mut self, bytes: Vec<u8>) -> Result<(), VmError> {
+        let image = ProgramImage::parse(&bytes)?;
+        self.program = bytes;
+        self.load_image(image);
+        Ok(())
+    }
+
+    /// Adds an arbitrary byte to the VM's program
+    pub fn add_byte(&mut self, b: u8) {
+        self.program.push(b);
+    }
+
+    /// Adds an arbitrary byte to the VM's program
+    pub fn add_bytes(&mut self, mut b: Vec<u8>) {
+        self.program.append(&mut b);
+    }
+
+    fn push_event(&mut self, event: VMEventType) {
+        self.events.push(VMEvent {
+            event,
+            at: Utc::now(),
+            application_id: self.id,
+        });
+    }
+
+    fn crash(&mut self, error: VmError) {
+        self.push_event(VMEventType::Crash { code: error.code(), error });
+    }
+
+    /// Points the VM at the sections of an image whose bytes are already in `program`
+    fn load_image(&mut self, image: ProgramImage) {
+        // Code addresses are absolute, so the PC starts right after the header and read-only section
+        self.pc = image.code_offset();
+        self.ro_data = image.ro_data;
+    }
+
+    /// Splits the program into its sections, then executes instructions until the program stops
+    fn execute_program(&mut self) -> Result<u32, VmError> {
+        let image = ProgramImage::parse(&self.program)?;
+        self.load_image(image);
+        loop {
+            if let Some(code) = self.execute_instruction()? {
+                return Ok(code);
+            }
+        }
+    }
+
+    /// Executes an instruction and returns the exit code once the program has stopped.
+    /// Meant to be called by the various public run functions.
+    fn execute_instruction(&mut self) -> Result<Option<u32>, VmError> {
+        if self.pc == self.program.len() {
+            return Ok(Some(1));
+        }
+        match self.decode_opcode()? {
+            Opcode::LOAD => {
+                let register = self.next_register()?;
+                let number = u32::from(self.next_16_bits()?);
+                self.registers[register] = number as i32;
+            }
+            Opcode::LOADHI => {
+                let register = self.next_register()?;
+                let high = u32::from(self.next_16_bits()?);
+                let low = self.registers[register] as u32 & 0xffff;
+                self.registers[register] = ((high << 16) | low) as i32;
+            }
+            Opcode::ADD => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1.wrapping_add(register2);
+            }
+            Opcode::SUB => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1.wrapping_sub(register2);
+            }
+            Opcode::MUL => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1.wrapping_mul(register2);
+            }
+            Opcode::DIV => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                if register2 == 0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                self.registers[self.next_register()?] = register1.wrapping_div(register2);
+                self.remainder = register1.wrapping_rem(register2) as usize;
+            }
+            Opcode::MOD => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                if register2 == 0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                self.registers[self.next_register()?] = register1.wrapping_rem(register2);
+            }
+            Opcode::AND => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1 & register2;
+            }
+            Opcode::OR => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1 | register2;
+            }
+            Opcode::XOR => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1 ^ register2;
+            }
+            Opcode::NOT => {
+                let register = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = !register;
+                self.pc += 1;
+            }
+            Opcode::SHL => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1.wrapping_shl(register2 as u32);
+            }
+            Opcode::SHR => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = (register1 as u32).wrapping_shr(register2 as u32) as i32;
+            }
+            Opcode::SAR => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1.wrapping_shr(register2 as u32);
+            }
+            Opcode::HLT => {
+                println!("HLT encountered");
+                return Ok(Some(0));
+            }
+            Opcode::JMP => {
+                let target = self.registers[self.next_register()?];
+                self.jump_to(i64::from(target))?;
+            }
+            Opcode::JMPF => {
+                let value = self.registers[self.next_register()?];
+                let target = self.pc as i64 + i64::from(value);
+                self.jump_to(target)?;
+            }
+            Opcode::JMPB => {
+                let value = self.registers[self.next_register()?];
+                let target = self.pc as i64 - i64::from(value);
+                self.jump_to(target)?;
+            }
+            Opcode::EQ => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 == register2;
+                self.pc += 1;
+            }
+            Opcode::NEQ => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 != register2;
+                self.pc += 1;
+            }
+            Opcode::GT => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 > register2;
+                self.pc += 1;
+            }
+            Opcode::GTE => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 >= register2;
+                self.pc += 1;
+            }
+            Opcode::LT => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 < register2;
+                self.pc += 1;
+            }
+            Opcode::LTE => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 <= register2;
+                self.pc += 1;
+            }
+            Opcode::JMPE => {
+                if self.equal_flag {
+                    let target = self.registers[self.next_register()?];
+                    self.jump_to(i64::from(target))?;
+                } else {
+                    self.pc += 3;
+                }
+            }
+            Opcode::NOP => {
+                self.pc += 3;
+            }
+            Opcode::ALOC => {
+                let bytes = self.registers[self.next_register()?];
+                if bytes < 0 {
+                    return Err(VmError::InvalidAllocation { size: bytes });
+                }
+                let new_end = self.heap.len() + bytes as usize;
+                if new_end > MAX_HEAP_SIZE {
+                    return Err(VmError::HeapOverflow { size: new_end });
+                }
+                self.heap.resize(new_end, 0);
+                self.pc += 2;
+            }
+            Opcode::FREE => {
+                let bytes = self.registers[self.next_register()?];
+                if bytes < 0 || bytes as usize > self.heap.len() {
+                    return Err(VmError::InvalidAllocation { size: bytes });
+                }
+                let new_end = self.heap.len() - bytes as usize;
+                self.heap.truncate(new_end);
+                self.pc += 2;
+            }
+            Opcode::INC => {
+                let register_number = self.next_register()?;
+                self.registers[register_number] = self.registers[register_number].wrapping_add(1);
+                self.pc += 2;
+            }
+            Opcode::DEC => {
+                let register_number = self.next_register()?;
+                self.registers[register_number] = self.registers[register_number].wrapping_sub(1);
+                self.pc += 2;
+            }
+            Opcode::DJMPE => {
+                let destination = self.next_16_bits()?;
+                if self.equal_flag {
+                    self.jump_to(i64::from(destination))?;
+                } else {
+                    self.pc += 1;
+                }
+            }
+            Opcode::DJMP => {
+                let destination = self.next_16_bits()?;
+                self.jump_to(i64::from(destination))?;
+            }
+            Opcode::PRTS => {
+                // PRTS takes one operand, either a starting index in the read-only section of the bytecode
+                // or a symbol (in the form of @symbol_name), which will look up the offset in the symbol table.
+                // This instruction then reads each byte and prints it, until it comes to a 0x00 byte, which indicates
+                // termination of the string
+                let starting_offset = self.next_16_bits()? as usize;
+                self.pc += 1;
+                let s = self.read_ro_string(starting_offset)?;
+                print!("{}", s);
+            }
+            Opcode::LOADF64 => {
+                let register = self.next_register()?;
+                let number = f64::from(self.next_16_bits()?);
+                self.float_registers[register] = number;
+            }
+            Opcode::LOADCF64 => {
+                let register = self.next_register()?;
+                let offset = self.next_16_bits()? as usize;
+                let bytes = match self.ro_data.get(offset..offset + 8) {
+                    Some(bytes) => bytes,
+                    None => return Err(VmError::RoDataOutOfBounds { offset }),
+                };
+                self.float_registers[register] = LittleEndian::read_f64(bytes);
+            }
+            Opcode::ADDF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = register1 + register2;
+            }
+            Opcode::SUBF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = register1 - register2;
+            }
+            Opcode::MULF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = register1 * register2;
+            }
+            Opcode::DIVF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = register1 / register2;
+            }
+            Opcode::EQF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.equal_flag = (register1 - register2).abs() < f64::EPSILON;
+                self.next_8_bits()?;
+            }
+            Opcode::NEQF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.equal_flag = (register1 - register2).abs() > f64::EPSILON;
+                self.next_8_bits()?;
+            }
+            Opcode::GTF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.equal_flag = register1 > register2;
+                self.next_8_bits()?;
+            }
+            Opcode::GTEF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.equal_flag = register1 >= register2;
+                self.next_8_bits()?;
+            }
+            Opcode::LTF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.equal_flag = register1 < register2;
+                self.next_8_bits()?;
+            }
+            Opcode::LTEF64 => {
+                let register1 = self.float_registers[self.next_register()?];
+                let register2 = self.float_registers[self.next_register()?];
+                self.equal_flag = register1 <= register2;
+                self.next_8_bits()?;
+            }
+            Opcode::CALL => {
+                let destination = self.next_16_bits()?;
+                // Return to the instruction following the call
+                let return_address = self.pc as u64 + 1;
+                self.push(return_address)?;
+                self.jump_to(i64::from(destination))?;
+            }
+            Opcode::RET => {
+                let return_address = self.pop()?;
+                self.jump_to(return_address as i64)?;
+            }
+            Opcode::PUSH => {
+                let value = self.registers[self.next_register()?];
+                self.push(value as u64)?;
+                self.pc += 2;
+            }
+            Opcode::POP => {
+                let register = self.next_register()?;
+                self.registers[register] = self.pop()? as i32;
+                self.pc += 2;
+            }
+            Opcode::PUSHF64 => {
+                let value = self.float_registers[self.next_register()?];
+                self.push(value.to_bits())?;
+                self.pc += 2;
+            }
+            Opcode::POPF64 => {
+                let register = self.next_register()?;
+                self.float_registers[register] = f64::from_bits(self.pop()?);
+                self.pc += 2;
+            }
+            Opcode::LOADB => {
+                let register = self.next_register()?;
+                let range = self.next_heap_range(1)?;
+                self.registers[register] = i32::from(self.heap[range.start]);
+            }
+            Opcode::LOADH => {
+                let register = self.next_register()?;
+                let range = self.next_heap_range(2)?;
+                self.registers[register] = i32::from(LittleEndian::read_u16(&self.heap[range]));
+            }
+            Opcode::LOADW => {
+                let register = self.next_register()?;
+                let range = self.next_heap_range(4)?;
+                self.registers[register] = LittleEndian::read_i32(&self.heap[range]);
+            }
+            Opcode::LOADD => {
+                let register = self.next_register()?;
+                let range = self.next_heap_range(8)?;
+                self.float_registers[register] = LittleEndian::read_f64(&self.heap[range]);
+            }
+            Opcode::STOREB => {
+                let value = self.registers[self.next_register()?];
+                let range = self.next_heap_range(1)?;
+                self.heap[range.start] = value as u8;
+            }
+            Opcode::STOREH => {
+                let value = self.registers[self.next_register()?];
+                let range = self.next_heap_range(2)?;
+                LittleEndian::write_u16(&mut self.heap[range], value as u16);
+            }
+            Opcode::STOREW => {
+                let value = self.registers[self.next_register()?];
+                let range = self.next_heap_range(4)?;
+                LittleEndian::write_i32(&mut self.heap[range], value);
+            }
+            Opcode::STORED => {
+                let value = self.float_registers[self.next_register()?];
+                let range = self.next_heap_range(8)?;
+                LittleEndian::write_f64(&mut self.heap[range], value);
+            }
+            Opcode::ITOF => {
+                let value = self.registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = f64::from(value);
+                self.pc += 1;
+            }
+            Opcode::FTOI => {
+                let value = self.float_registers[self.next_register()?];
+                let register = self.next_register()?;
+                let rounded = match self.next_8_bits()? {
+                    0 => value.trunc(),
+                    1 => value.round(),
+                    2 => value.floor(),
+                    3 => value.ceil(),
+                    mode => return Err(VmError::InvalidConversionMode { mode }),
+                };
+                // Out of range values saturate and NaN becomes 0
+                self.registers[register] = rounded as i32;
+            }
+            Opcode::MOV => {
+                let value = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = value;
+                self.pc += 1;
+            }
+            Opcode::MOVF64 => {
+                let value = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = value;
+                self.pc += 1;
+            }
+            Opcode::NEGF64 => {
+                let value = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = -value;
+                self.pc += 1;
+            }
+            Opcode::ABSF64 => {
+                let value = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = value.abs();
+                self.pc += 1;
+            }
+            Opcode::SQRTF64 => {
+                let value = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = value.sqrt();
+                self.pc += 1;
+            }
+            Opcode::IGL => {
+                return Err(VmError::IllegalOpcode { opcode: self.program[self.pc - 1] });
+            }
+        };
+
+        Ok(None)
+    }
+
+    /// Attempts to decode the byte the VM's program counter is pointing at into an opcode
+    fn decode_opcode(&mut self) -> Result<Opcode, VmError> {
+        Ok(Opcode::from(self.next_8_bits()?))
+    }
+
+    /// Attempts to decode the next byte into an opcode
+    fn next_8_bits(&mut self) -> Result<u8, VmError> {
+        let result = match self.program.get(self.pc) {
+            Some(byte) => *byte,
+            None => return Err(VmError::PcOutOfBounds { pc: self.pc }),
+        };
+        self.pc += 1;
+        Ok(result)
+    }
+
+    /// Grabs the next 16 bits (2 bytes)
+    fn next_16_bits(&mut self) -> Result<u16, VmError> {
+        let high = self.next_8_bits()?;
+        let low = self.next_8_bits()?;
+        Ok((u16::from(high) << 8) | u16::from(low))
+    }
+
+    /// Grabs the next byte and checks that it names an existing register
+    fn next_register(&mut self) -> Result<usize, VmError> {
+        let register = self.next_8_bits()?;
+        if register as usize >= self.registers.len() {
+            return Err(VmError::InvalidRegister { register });
+        }
+        Ok(register as usize)
+    }
+
+    /// Reads a base register and an 8 bits offset, and returns the heap bytes they address
+    fn next_heap_range(&mut self, size: usize) -> Result<Range<usize>, VmError> {
+        let base = self.registers[self.next_register()?];
+        let offset = self.next_8_bits()?;
+        let address = i64::from(base) + i64::from(offset);
+        if address < 0 || address as u64 + size as u64 > self.heap.len() as u64 {
+            return Err(VmError::HeapOutOfBounds { address, size });
+        }
+        let start = address as usize;
+        Ok(start..start + size)
+    }
+
+    /// Pushes a slot onto the stack segment
+    fn push(&mut self, value: u64) -> Result<(), VmError> {
+        if self.stack.len() >= MAX_STACK_SIZE {
+            return Err(VmError::StackOverflow);
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Pops a slot from the stack segment
+    fn pop(&mut self) -> Result<u64, VmError> {
+        self.stack.pop().ok_or(VmError::StackUnderflow)
+    }
+
+    /// Moves the program counter, making sure it stays inside the program
+    fn jump_to(&mut self, target: i64) -> Result<(), VmError> {
+        if target < 0 || target as u64 > self.program.len() as u64 {
+            return Err(VmError::InvalidJumpTarget { target });
+        }
+        self.pc = target as usize;
+        Ok(())
+    }
+
+    /// Reads a null terminated string out of the read-only section
+    fn read_ro_string(&self, starting_offset: usize) -> Result<&str, VmError> {
+        // TODO: Find a better way to do this. Maybe we can store the byte length and not null terminate? Or some form of caching where we
+        // go through the entire ro_data on VM startup and find every string and its ending byte location?
+        let slice = match self.ro_data.get(starting_offset..) {
+            Some(slice) => slice,
+            None => return Err(VmError::RoDataOutOfBounds { offset: starting_offset }),
+        };
+        let length = match slice.iter().position(|byte| *byte == 0) {
+            Some(length) => length,
+            None => return Err(VmError::RoDataOutOfBounds { offset: starting_offset }),
+        };
+        std::str::from_utf8(&slice[..length])
+            .map_err(|_| VmError::InvalidUtf8 { offset: starting_offset })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::bool_assert_comparison)]
+
+    use assembler::{Assembler, PIE_HEADER_LENGTH, PIE_HEADER_PREFIX};
+    use super::*;
+
+    fn get_test_vm() -> VM {
+        let mut vm = VM::new();
+        vm.registers[0] = 5;
+        vm.registers[1] = 10;
+        vm.float_registers[0] = 5.0;
+        vm.float_registers[1] = 10.0;
+        vm
+    }
+
+    fn prepend_header(mut b: Vec<u8>) -> Vec<u8> {
+        let mut prepension = PIE_HEADER_PREFIX.to_vec();
+        while prepension.len() < PIE_HEADER_LENGTH {
+            prepension.push(0);
+        }
+        prepension.append(&mut b);
+        prepension
+    }
+
+    #[test]
+    fn test_create_vm() {
+        let test_vm = VM::new();
+        assert_eq!(test_vm.registers[0], 0)
+    }
+
+    #[test]
+    fn test_opcode_hlt() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![5, 0, 0, 0];
+        test_vm.program = test_bytes;
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_opcode_igl() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![254, 0, 0, 0];
+        test_vm.program = test_bytes;
+        test_vm.run_once();
+        // The illegal opcode is not skipped
+        assert_eq!(test_vm.pc, 0);
+    }
+
+    #[test]
+    fn test_load_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![0, 0, 1, 244];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[0], 500);
+    }
+
+    #[test]
+    fn test_loadhi_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![0, 0, 0x11, 0x70, 57, 0, 0x00, 0x01];
+        test_vm.run_once();
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 70000);
+    }
+
+    #[test]
+    fn test_add_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![1, 0, 1, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], 15);
+    }
+
+    #[test]
+    fn test_sub_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![2, 1, 0, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], 5);
+    }
+
+    #[test]
+    fn test_mul_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![3, 0, 1, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], 50);
+    }
+
+    #[test]
+    fn test_div_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![4, 1, 0, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], 2);
+    }
+
+    #[test]
+    fn test_mod_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -7;
+        test_vm.registers[1] = 3;
+        test_vm.program = vec![56, 0, 1, 2];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], -1);
+        test_vm.registers[1] = 0;
+        test_vm.pc = 0;
+        assert_eq!(test_vm.step(), Err(VmError::DivisionByZero));
+    }
+
+    #[test]
+    fn test_bitwise_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 0b1100;
+        test_vm.registers[1] = 0b1010;
+        test_vm.program = vec![49, 0, 1, 2, 50, 0, 1, 3, 51, 0, 1, 4, 52, 0, 5, 0];
+        for _ in 0..4 {
+            test_vm.run_once();
+        }
+        assert_eq!(test_vm.registers[2], 0b1000);
+        assert_eq!(test_vm.registers[3], 0b1110);
+        assert_eq!(test_vm.registers[4], 0b0110);
+        assert_eq!(test_vm.registers[5], !0b1100);
+        assert_eq!(test_vm.pc, 16);
+    }
+
+    #[test]
+    fn test_shift_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -16;
+        test_vm.registers[1] = 2;
+        test_vm.program = vec![53, 0, 1, 2, 54, 0, 1, 3, 55, 0, 1, 4];
+        for _ in 0..3 {
+            test_vm.run_once();
+        }
+        assert_eq!(test_vm.registers[2], -64);
+        assert_eq!(test_vm.registers[3], (-16i32 as u32 >> 2) as i32);
+        assert_eq!(test_vm.registers[4], -4);
+    }
+
+    #[test]
+    fn test_jmp_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 4;
+        test_vm.program = vec![6, 0, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpf_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 2;
+        test_vm.program = vec![7, 0, 0, 0, 5, 0, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpb_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 6;
+        test_vm.program = vec![0, 0, 0, 10, 8, 1, 0, 0];
+        test_vm.run_once();
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 0);
+    }
+
+    #[test]
+    fn test_eq_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![9, 0, 1, 0, 9, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[1] = 20;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_neq_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 20;
+        test_vm.program = vec![10, 0, 1, 0, 10, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[1] = 10;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+
+    #[test]
+    fn test_gt_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 20;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![11, 0, 1, 0, 11, 0, 1, 0, 11, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[0] = 10;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.registers[0] = 5;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_lt_opcode() {
+        let mut vm = get_test_vm();
+        vm.registers[0] = 20;
+        vm.registers[1] = 10;
+        vm.program = vec![12, 0, 1, 0, 12, 0, 1, 0, 12, 0, 1, 0];
+        vm.run_once();
+        assert_eq!(vm.equal_flag, false);
+        vm.registers[0] = 10;
+        vm.run_once();
+        assert_eq!(vm.equal_flag, false);
+        vm.registers[0] = 5;
+        vm.run_once();
+        assert_eq!(vm.equal_flag, true);
+    }
+
+    #[test]
+    fn test_gte_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 20;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![13, 0, 1, 0, 13, 0, 1, 0, 13, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[0] = 10;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[0] = 5;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_lte_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 20;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![14, 0, 1, 0, 14, 0, 1, 0, 14, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.registers[0] = 10;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[0] = 5;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+    }
+
+    #[test]
+    fn test_jmpe_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 7;
+        test_vm.equal_flag = true;
+        test_vm.program = vec![15, 0, 0, 0, 15, 0, 0, 0, 15, 0, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 7);
+    }
+
+    #[test]
+    fn test_jmpe_opcode_falls_through() {
+        let mut test_vm = get_test_vm();
+        test_vm.equal_flag = false;
+        test_vm.program = vec![15, 0, 0, 0, 5, 0, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_djmp_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![33, 0, 8, 0, 5, 0, 0, 0, 5, 0, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 8);
+    }
+
+    #[test]
+    fn test_aloc_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 1024;
+        test_vm.program = vec![17, 0, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.heap.len(), 1024);
+    }
+
+    #[test]
+    fn test_free_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 16;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![17, 0, 0, 0, 48, 1, 0, 0, 48, 1, 0, 0];
+        test_vm.run_once();
+        test_vm.run_once();
+        assert_eq!(test_vm.heap.len(), 6);
+        assert_eq!(test_vm.pc, 8);
+        assert_eq!(test_vm.step(), Err(VmError::InvalidAllocation { size: 10 }));
+    }
+
+    #[test]
+    fn test_heap_store_load_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 32];
+        test_vm.registers[0] = -2;
+        test_vm.registers[1] = 8;
+        test_vm.float_registers[0] = 3.25;
+        test_vm.program = vec![
+            46, 0, 1, 0, // storew $0 $1 #0
+            44, 0, 1, 4, // storeb $0 $1 #4
+            45, 0, 1, 6, // storeh $0 $1 #6
+            47, 0, 1, 8, // stored $0 $1 #8
+            42, 2, 1, 0, // loadw $2 $1 #0
+            40, 3, 1, 4, // loadb $3 $1 #4
+            41, 4, 1, 6, // loadh $4 $1 #6
+            43, 5, 1, 8, // loadd $5 $1 #8
+        ];
+        for _ in 0..8 {
+            test_vm.run_once();
+        }
+        assert_eq!(test_vm.registers[2], -2);
+        assert_eq!(test_vm.registers[3], 0xfe);
+        assert_eq!(test_vm.registers[4], 0xfffe);
+        assert_eq!(test_vm.float_registers[5], 3.25);
+        assert_eq!(&test_vm.heap[8..12], &[0xfe, 0xff, 0xff, 0xff]);
+        assert_eq!(test_vm.events().len(), 0);
+    }
+
+    #[test]
+    fn test_heap_access_out_of_bounds() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 8];
+        test_vm.registers[1] = 6;
+        test_vm.program = vec![42, 0, 1, 0, 40, 0, 1, 2];
+        assert_eq!(test_vm.step(), Err(VmError::HeapOutOfBounds { address: 6, size: 4 }));
+        test_vm.pc = 4;
+        assert_eq!(test_vm.step(), Err(VmError::HeapOutOfBounds { address: 8, size: 1 }));
+    }
+
+    #[test]
+    fn test_prts_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.ro_data.append(&mut vec![72, 101, 108, 108, 111, 0]);
+        test_vm.program = vec![21, 0, 0, 0];
+        test_vm.run_once();
+        // TODO: How can we validate the output since it is just printing to stdout in a test?
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_call_ret_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![34, 0, 8, 0, 5, 0, 0, 0, 35, 0, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 8);
+        assert_eq!(test_vm.stack(), &[4]);
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 4);
+        assert_eq!(test_vm.stack().len(), 0);
+    }
+
+    #[test]
+    fn test_push_pop_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -5;
+        test_vm.float_registers[0] = 2.5;
+        test_vm.program = vec![36, 0, 0, 0, 38, 0, 0, 0, 39, 2, 0, 0, 37, 2, 0, 0];
+        for _ in 0..4 {
+            test_vm.run_once();
+        }
+        assert_eq!(test_vm.float_registers[2], 2.5);
+        assert_eq!(test_vm.registers[2], -5);
+        assert_eq!(test_vm.pc, 16);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![35, 0, 0, 0];
+        assert_eq!(test_vm.step(), Err(VmError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut test_vm = get_test_vm();
+        test_vm.stack = vec![0; MAX_STACK_SIZE];
+        test_vm.program = vec![36, 0, 0, 0];
+        assert_eq!(test_vm.step(), Err(VmError::StackOverflow));
+    }
+
+    #[test]
+    fn test_load_program_splits_sections() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+        .data
+        hello: .asciiz 'Hello'
+        world: .asciiz 'World'
+        .code
+        prts @world
+        hlt
+        ").unwrap();
+        let mut test_vm = VM::new();
+        assert_eq!(test_vm.load_program(program), Ok(()));
+        assert_eq!(test_vm.ro_data, b"Hello\0World\0".to_vec());
+        assert_eq!(test_vm.pc, PIE_HEADER_LENGTH + 12);
+        assert_eq!(test_vm.read_ro_string(6), Ok("World"));
+        let events = test_vm.run();
+        match events.last().map(|e| e.event()) {
+            Some(VMEventType::GracefulStop { code }) => assert_eq!(*code, 0),
+            other => panic!("Expected a graceful stop, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_program_rejects_bad_header() {
+        let mut test_vm = VM::new();
+        assert_eq!(test_vm.load_program(vec![1, 2, 3, 4]), Err(VmError::InvalidHeader));
+    }
+
+    #[test]
+    fn test_load_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![22, 0, 1, 244];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.float_registers[0], 500.0);
+    }
+
+    #[test]
+    fn test_load_constant_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.ro_data = vec![0; 2];
+        test_vm.ro_data.extend_from_slice(&(-2.75f64).to_bits().to_le_bytes());
+        test_vm.program = vec![58, 1, 0, 2, 58, 1, 0, 3];
+        test_vm.run_once();
+        assert_eq!(test_vm.float_registers[1], -2.75);
+        assert_eq!(test_vm.pc, 4);
+        assert_eq!(test_vm.step(), Err(VmError::RoDataOutOfBounds { offset: 3 }));
+    }
+
+    #[test]
+    fn test_itof_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -7;
+        test_vm.program = vec![59, 0, 3, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.float_registers[3], -7.0);
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_ftoi_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.float_registers[0] = -2.5;
+        test_vm.float_registers[1] = 1e20;
+        test_vm.program = vec![
+            60, 0, 2, 0,
+            60, 0, 3, 1,
+            60, 0, 4, 2,
+            60, 0, 5, 3,
+            60, 1, 6, 0,
+            60, 0, 7, 9,
+        ];
+        for _ in 0..5 {
+            test_vm.run_once();
+        }
+        assert_eq!(test_vm.registers[2], -2);
+        assert_eq!(test_vm.registers[3], -3);
+        assert_eq!(test_vm.registers[4], -3);
+        assert_eq!(test_vm.registers[5], -2);
+        assert_eq!(test_vm.registers[6], i32::MAX);
+        assert_eq!(test_vm.step(), Err(VmError::InvalidConversionMode { mode: 9 }));
+    }
+
+    #[test]
+    fn test_mov_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![61, 1, 2, 0, 62, 1, 2, 0];
+        test_vm.run_once();
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], 10);
+        assert_eq!(test_vm.float_registers[2], 10.0);
+        assert_eq!(test_vm.pc, 8);
+    }
+
+    #[test]
+    fn test_unary_floating_point_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.float_registers[0] = -16.0;
+        test_vm.program = vec![63, 0, 1, 0, 64, 0, 2, 0, 65, 1, 3, 0];
+        for _ in 0..3 {
+            test_vm.run_once();
+        }
+        assert_eq!(test_vm.float_registers[1], 16.0);
+        assert_eq!(test_vm.float_registers[2], 16.0);
+        assert_eq!(test_vm.float_registers[3], 4.0);
+    }
+
+    #[test]
+    fn test_add_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![23, 0, 1, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.float_registers[2], 15.0);
+    }
+
+    #[test]
+    fn test_sub_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![24, 1, 0, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.float_registers[2], 5.0);
+    }
+
+    #[test]
+    fn test_mul_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![25, 1, 0, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.float_registers[2], 50.0);
+    }
+
+    #[test]
+    fn test_div_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![26, 1, 0, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.float_registers[2], 2.0);
+    }
+
+    #[test]
+    fn test_eq_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.float_registers[0] = 10.0;
+        test_vm.float_registers[1] = 10.0;
+        test_vm.program = vec![27, 0, 1, 0, 27, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.float_registers[1] = 20.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_neq_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.float_registers[0] = 10.0;
+        test_vm.float_registers[1] = 20.0;
+        test_vm.program = vec![28, 0, 1, 0, 28, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.float_registers[1] = 10.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_gt_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.float_registers[0] = 20.0;
+        test_vm.float_registers[1] = 10.0;
+        test_vm.program = vec![29, 0, 1, 0, 29, 0, 1, 0, 29, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.float_registers[0] = 10.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.float_registers[0] = 5.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_gte_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.float_registers[0] = 20.0;
+        test_vm.float_registers[1] = 10.0;
+        test_vm.program = vec![30, 0, 1, 0, 30, 0, 1, 0, 30, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.float_registers[0] = 10.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.float_registers[0] = 5.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_lt_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.float_registers[0] = 20.0;
+        test_vm.float_registers[1] = 10.0;
+        test_vm.program = vec![31, 0, 1, 0, 31, 0, 1, 0, 31, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.float_registers[0] = 10.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.float_registers[0] = 5.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+    }
+
+    #[test]
+    fn test_lte_floating_point_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.float_registers[0] = 20.0;
+        test_vm.float_registers[1] = 10.0;
+        test_vm.program = vec![32, 0, 1, 0, 32, 0, 1, 0, 32, 0, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.float_registers[0] = 10.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.float_registers[0] = 5.0;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+    }
+
+    #[test]
+    fn test_invalid_register_is_an_error() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![1, 0, 200, 2];
+        assert_eq!(test_vm.step(), Err(VmError::InvalidRegister { register: 200 }));
+    }
+
+    #[test]
+    fn test_truncated_instruction_is_an_error() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![0, 0, 1];
+        assert_eq!(test_vm.step(), Err(VmError::PcOutOfBounds { pc: 3 }));
+    }
+
+    #[test]
+    fn test_div_by_zero_is_an_error() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 0;
+        test_vm.program = vec![4, 0, 1, 2];
+        assert_eq!(test_vm.step(), Err(VmError::DivisionByZero));
+        // The PC is left on the instruction that failed
+        assert_eq!(test_vm.pc(), 0);
+    }
+
+    #[test]
+    fn test_illegal_opcode_is_an_error() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![254, 0, 0, 0];
+        assert_eq!(test_vm.step(), Err(VmError::IllegalOpcode { opcode: 254 }));
+    }
+
+    #[test]
+    fn test_jump_outside_program_is_an_error() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -4;
+        test_vm.program = vec![6, 0, 0, 0];
+        assert_eq!(test_vm.step(), Err(VmError::InvalidJumpTarget { target: -4 }));
+    }
+
+    #[test]
+    fn test_prts_out_of_bounds_is_an_error() {
+        let mut test_vm = get_test_vm();
+        test_vm.ro_data.append(&mut vec![72, 101]);
+        test_vm.program = vec![21, 0, 0, 0, 21, 0, 9, 0];
+        assert_eq!(test_vm.step(), Err(VmError::RoDataOutOfBounds { offset: 0 }));
+        test_vm.pc = 4;
+        assert_eq!(test_vm.step(), Err(VmError::RoDataOutOfBounds { offset: 9 }));
+    }
+
+    #[test]
+    fn test_prts_invalid_utf8_is_an_error() {
+        let mut test_vm = get_test_vm();
+        test_vm.ro_data.append(&mut vec![0xff, 0xfe, 0]);
+        test_vm.program = vec![21, 0, 0, 0];
+        assert_eq!(test_vm.step(), Err(VmError::InvalidUtf8 { offset: 0 }));
+    }
+
+    #[test]
+    fn test_aloc_limits() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -1;
+        test_vm.program = vec![17, 0, 0, 0];
+        assert_eq!(test_vm.step(), Err(VmError::InvalidAllocation { size: -1 }));
+        test_vm.registers[0] = (MAX_HEAP_SIZE + 1) as i32;
+        test_vm.pc = 0;
+        assert_eq!(test_vm.step(), Err(VmError::HeapOverflow { size: MAX_HEAP_SIZE + 1 }));
+    }
+
+    #[test]
+    fn test_run_records_crash() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 0;
+        test_vm.program = prepend_header(vec![4, 0, 1, 2]);
+        let events = test_vm.run();
+        match events.last().map(|e| e.event()) {
+            Some(VMEventType::Crash { code, error }) => {
+                assert_eq!(*code, 6);
+                assert_eq!(*error, VmError::DivisionByZero);
+            }
+            other => panic!("Expected a crash, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_run_with_short_header_crashes() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![0x45, 0x50];
+        let events = test_vm.run();
+        match events.last().map(|e| e.event()) {
+            Some(VMEventType::Crash { code, .. }) => assert_eq!(*code, 1),
+            other => panic!("Expected a crash, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_run_once_records_crash() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![254, 0, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.events().len(), 1);
+    }
+}